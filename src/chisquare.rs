@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::default::Default;
+use std::hash::Hash;
+use std::iter::{FromIterator, IntoIterator};
+
+use Commute;
+
+/// The result of a chi-square or G-test.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChiSquare {
+    /// The test statistic.
+    pub statistic: f64,
+    /// The degrees of freedom of the chi-square distribution the statistic
+    /// is compared against.
+    pub df: usize,
+    /// The probability of a statistic at least this large if the null
+    /// hypothesis is true.
+    pub p_value: f64,
+}
+
+impl ChiSquare {
+    /// Computes Pearson's chi-square test from pairs of observed and
+    /// expected counts.
+    pub(crate) fn pearson<I>(cells: I, df: usize) -> ChiSquare
+        where I: Iterator<Item=(f64, f64)> {
+        let statistic = cells.map(|(o, e)| (o - e) * (o - e) / e).sum();
+        ChiSquare::new(statistic, df)
+    }
+
+    /// Computes the G-test, or likelihood ratio test, from pairs of
+    /// observed and expected counts.
+    pub(crate) fn g_test<I>(cells: I, df: usize) -> ChiSquare
+        where I: Iterator<Item=(f64, f64)> {
+        let statistic = 2.0 * cells.filter(|&(o, _)| o > 0.0)
+                                   .map(|(o, e)| o * (o / e).ln())
+                                   .sum::<f64>();
+        ChiSquare::new(statistic, df)
+    }
+
+    fn new(statistic: f64, df: usize) -> ChiSquare {
+        let p_value = if df == 0 {
+            1.0
+        } else {
+            gamma_q(df as f64 / 2.0, statistic / 2.0)
+        };
+        ChiSquare { statistic, df, p_value }
+    }
+}
+
+/// Returns the natural logarithm of the gamma function for `x > 0`.
+fn ln_gamma(x: f64) -> f64 {
+    // The Lanczos approximation with `g = 7`, accurate to about 15 digits.
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // The reflection formula.
+        let pi = ::std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+    let x = x - 1.0;
+    let mut sum = COEFFICIENTS[0];
+    for (i, &c) in COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += c / (x + i as f64);
+    }
+    let t = x + 7.5;
+    0.5 * (2.0 * ::std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t
+        + sum.ln()
+}
+
+/// Returns the regularized upper incomplete gamma function `Q(a, x)`.
+fn gamma_q(a: f64, x: f64) -> f64 {
+    if x.is_nan() {
+        return f64::NAN;
+    }
+    if x <= 0.0 {
+        return 1.0;
+    }
+    if x == f64::INFINITY {
+        return 0.0;
+    }
+    let scale = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        // The series for `P(a, x)` converges quickly here.
+        let (mut ap, mut term) = (a, 1.0 / a);
+        let mut sum = term;
+        while term.abs() > sum.abs() * 1e-16 {
+            ap += 1.0;
+            term *= x / ap;
+            sum += term;
+        }
+        1.0 - sum * scale
+    } else {
+        // Otherwise, evaluate the continued fraction for `Q(a, x)` with
+        // the modified Lentz method.
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        let mut i = 1.0;
+        loop {
+            let an = -i * (i - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-16 || i > 1000.0 {
+                return h * scale;
+            }
+            i += 1.0;
+        }
+    }
+}
+
+/// A commutative two-way table of counts for testing whether two
+/// categorical variables are independent.
+///
+/// Rows and columns are kept in the order their values were first seen, so
+/// the statistics of a table built from the same data are always the same.
+#[derive(Clone, Debug)]
+pub struct Contingency<A, B> {
+    total: u64,
+    // Each row and column value with its total, in the order first seen.
+    rows: Vec<(A, u64)>,
+    cols: Vec<(B, u64)>,
+    row_index: HashMap<A, usize>,
+    col_index: HashMap<B, usize>,
+    // Maps the indices of a row and a column to the count of their cell.
+    cells: HashMap<(usize, usize), u64>,
+}
+
+impl<A, B> Contingency<A, B>
+        where A: Eq + Hash + Clone, B: Eq + Hash + Clone {
+    /// Create an empty table.
+    pub fn new() -> Contingency<A, B> {
+        Default::default()
+    }
+
+    /// Add an observation of the pair `(a, b)` to the table.
+    pub fn add(&mut self, a: A, b: B) {
+        self.add_count(a, b, 1);
+    }
+
+    fn add_count(&mut self, a: A, b: B, n: u64) {
+        let i = index_of(&mut self.row_index, &mut self.rows, a);
+        let j = index_of(&mut self.col_index, &mut self.cols, b);
+        self.total += n;
+        self.rows[i].1 += n;
+        self.cols[j].1 += n;
+        *self.cells.entry((i, j)).or_insert(0) += n;
+    }
+
+    /// Returns the number of observations of the pair `(a, b)`.
+    pub fn count(&self, a: &A, b: &B) -> u64 {
+        match (self.row_index.get(a), self.col_index.get(b)) {
+            (Some(&i), Some(&j)) => self.cell(i, j),
+            _ => 0,
+        }
+    }
+
+    fn cell(&self, i: usize, j: usize) -> u64 {
+        self.cells.get(&(i, j)).cloned().unwrap_or(0)
+    }
+
+    /// Returns the number of observations.
+    pub fn len(&self) -> usize {
+        self.total as usize
+    }
+
+    /// Returns true if there are no observations.
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Returns the `(observed, expected)` count of every cell, where the
+    /// expected counts assume the variables are independent.
+    fn cells(&self) -> Vec<(f64, f64)> {
+        let n = self.total as f64;
+        let mut cells = Vec::with_capacity(self.rows.len() * self.cols.len());
+        for (i, &(_, row)) in self.rows.iter().enumerate() {
+            for (j, &(_, col)) in self.cols.iter().enumerate() {
+                let observed = self.cell(i, j) as f64;
+                cells.push((observed, row as f64 * col as f64 / n));
+            }
+        }
+        cells
+    }
+
+    fn df(&self) -> usize {
+        self.rows.len().saturating_sub(1) * self.cols.len().saturating_sub(1)
+    }
+
+    /// Returns Pearson's chi-square test of independence.
+    pub fn chi_square(&self) -> ChiSquare {
+        ChiSquare::pearson(self.cells().into_iter(), self.df())
+    }
+
+    /// Returns the G-test of independence.
+    pub fn g_test(&self) -> ChiSquare {
+        ChiSquare::g_test(self.cells().into_iter(), self.df())
+    }
+
+    /// Returns Cramér's V, a measure of association between the two
+    /// variables in `[0, 1]`.
+    ///
+    /// This is `NaN` if either variable has fewer than two categories.
+    pub fn cramers_v(&self) -> f64 {
+        let k = self.rows.len().min(self.cols.len());
+        if k < 2 {
+            return f64::NAN;
+        }
+        let chi = self.chi_square().statistic;
+        (chi / (self.total as f64 * (k - 1) as f64)).sqrt()
+    }
+}
+
+/// Returns the index of `k` in `totals`, adding it with a total of `0` if
+/// it hasn't been seen.
+fn index_of<K>(index: &mut HashMap<K, usize>, totals: &mut Vec<(K, u64)>,
+               k: K) -> usize
+        where K: Eq + Hash + Clone {
+    let next = totals.len();
+    *index.entry(k.clone()).or_insert_with(|| {
+        totals.push((k, 0));
+        next
+    })
+}
+
+impl<A, B> Commute for Contingency<A, B>
+        where A: Eq + Hash + Clone, B: Eq + Hash + Clone {
+    fn merge(&mut self, v: Contingency<A, B>) {
+        // Add the rows and columns first so that new ones keep the order
+        // `v` saw them in.
+        let rows: Vec<usize> = v.rows.into_iter().map(|(a, _)| {
+            index_of(&mut self.row_index, &mut self.rows, a)
+        }).collect();
+        let cols: Vec<usize> = v.cols.into_iter().map(|(b, _)| {
+            index_of(&mut self.col_index, &mut self.cols, b)
+        }).collect();
+        for ((vi, vj), n) in v.cells {
+            let (i, j) = (rows[vi], cols[vj]);
+            self.rows[i].1 += n;
+            self.cols[j].1 += n;
+            *self.cells.entry((i, j)).or_insert(0) += n;
+        }
+        self.total += v.total;
+    }
+}
+
+impl<A: Eq + Hash, B: Eq + Hash> Default for Contingency<A, B> {
+    fn default() -> Contingency<A, B> {
+        Contingency {
+            total: 0,
+            rows: vec![],
+            cols: vec![],
+            row_index: HashMap::new(),
+            col_index: HashMap::new(),
+            cells: HashMap::new(),
+        }
+    }
+}
+
+impl<A, B> FromIterator<(A, B)> for Contingency<A, B>
+        where A: Eq + Hash + Clone, B: Eq + Hash + Clone {
+    fn from_iter<I: IntoIterator<Item=(A, B)>>(it: I) -> Contingency<A, B> {
+        let mut v = Contingency::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<A, B> Extend<(A, B)> for Contingency<A, B>
+        where A: Eq + Hash + Clone, B: Eq + Hash + Clone {
+    fn extend<I: IntoIterator<Item=(A, B)>>(&mut self, it: I) {
+        for (a, b) in it {
+            self.add(a, b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use testutil::close;
+    use super::{Contingency, gamma_q};
+
+    #[test]
+    fn incomplete_gamma() {
+        // The chi-square critical values at 5%.
+        assert!(close(gamma_q(0.5, 3.841458820694124 / 2.0), 0.05));
+        assert!(close(gamma_q(2.5, 11.070497693516351 / 2.0), 0.05));
+        assert!(close(gamma_q(50.0, 124.34211340400407 / 2.0), 0.05));
+        assert_eq!(gamma_q(3.0, 0.0), 1.0);
+        assert!(close(gamma_q(1.0, 2.0), (-2.0f64).exp()));
+    }
+
+    fn table() -> Contingency<&'static str, bool> {
+        let mut rows = vec![];
+        rows.extend(vec![("a", true); 10]);
+        rows.extend(vec![("a", false); 20]);
+        rows.extend(vec![("b", true); 30]);
+        rows.extend(vec![("b", false); 40]);
+        rows.into_iter().collect()
+    }
+
+    #[test]
+    fn independence() {
+        let (table, other) = (table(), table());
+        assert_eq!(table.len(), 100);
+        assert_eq!(table.count(&"b", &true), 30);
+        let chi = table.chi_square();
+        assert_eq!(chi.df, 1);
+        assert!(close(chi.statistic, 0.7936507936507936));
+        assert!(close(chi.p_value, 0.37299848361348714));
+        let g = table.g_test();
+        assert!(close(g.statistic, 0.8043486460964835));
+        assert!(close(g.p_value, 0.36979636792989634));
+        assert!(close(table.cramers_v(), 0.0890870806374748));
+        // Each table hashes differently, but sums in the same order.
+        assert_eq!(table.chi_square(), other.chi_square());
+        assert_eq!(table.g_test(), other.g_test());
+    }
+
+    #[test]
+    fn degenerate() {
+        let table: Contingency<u8, u8> =
+            vec![(1, 1), (1, 2)].into_iter().collect();
+        assert_eq!(table.chi_square().df, 0);
+        assert_eq!(table.chi_square().p_value, 1.0);
+        assert!(table.cramers_v().is_nan());
+    }
+
+    #[test]
+    fn merge() {
+        let halves = vec![
+            vec![("a", true); 10].into_iter()
+                .chain(vec![("b", false); 40]).collect(),
+            vec![("a", false); 20].into_iter()
+                .chain(vec![("b", true); 30]).collect(),
+        ];
+        let mut merged: Contingency<_, _> =
+            merge_all(halves.into_iter()).unwrap();
+        merged.merge(Contingency::new());
+        assert_eq!(merged.len(), 100);
+        assert_eq!(merged.count(&"a", &false), 20);
+        assert!(close(merged.chi_square().statistic,
+                      table().chi_square().statistic));
+    }
+}
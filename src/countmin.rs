@@ -0,0 +1,339 @@
+use std::collections::hash_map::DefaultHasher;
+use std::default::Default;
+use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::iter::{FromIterator, IntoIterator};
+use std::marker::PhantomData;
+
+use Commute;
+use rng::Rng;
+
+/// A commutative sketch for approximate frequency counts in fixed memory.
+///
+/// This is the Count-Min sketch of Cormode and Muthukrishnan (2005). Each
+/// sample increments one counter in each of `depth` rows of `width`
+/// counters, and the count of a value is the smallest of its counters.
+/// Counts are never underestimated, and with `with_error(epsilon, delta)`
+/// they are overestimated by more than `epsilon * total()` with probability
+/// at most `delta`.
+///
+/// With conservative update, only the smallest counters of a sample are
+/// incremented. This never makes counts less accurate and is usually much
+/// more accurate for skewed data.
+///
+/// Sketches can only be merged if they have the same dimensions and seed.
+/// `try_merge` reports a mismatch as an error, even if one of the sketches
+/// is empty. The `Commute` implementation can't, so it leaves the sketch
+/// unchanged and records the error instead, which is returned by
+/// `merge_error`. It also skips the check when either sketch is empty, so
+/// that `CountMinSketch::default()` can be merged with any sketch.
+#[derive(Clone, Debug)]
+pub struct CountMinSketch<T> {
+    width: usize,
+    depth: usize,
+    seed: u64,
+    conservative: bool,
+    total: u64,
+    // Row major, `depth` rows of `width` counters.
+    counters: Vec<u64>,
+    error: Option<CountMinMergeError>,
+    _marker: PhantomData<fn(&T)>,
+}
+
+/// The reason two `CountMinSketch`es could not be merged.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CountMinMergeError {
+    /// The sketches have different `(width, depth)`.
+    Dimensions((usize, usize), (usize, usize)),
+    /// The sketches hash values with different seeds.
+    Seed(u64, u64),
+}
+
+impl fmt::Display for CountMinMergeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CountMinMergeError::Dimensions(a, b) => {
+                write!(f, "cannot merge sketches with dimensions {:?} and \
+                           {:?}", a, b)
+            }
+            CountMinMergeError::Seed(a, b) => {
+                write!(f, "cannot merge sketches with seeds {} and {}", a, b)
+            }
+        }
+    }
+}
+
+impl error::Error for CountMinMergeError {}
+
+impl<T: Hash> CountMinSketch<T> {
+    /// Create an empty sketch that overestimates counts by at most `0.1%`
+    /// of the total with probability `99%`.
+    pub fn new() -> CountMinSketch<T> {
+        Default::default()
+    }
+
+    /// Create an empty sketch with `depth` rows of `width` counters.
+    pub fn with_dimensions(width: usize, depth: usize) -> CountMinSketch<T> {
+        CountMinSketch::with_seed(width, depth, 0)
+    }
+
+    /// Create an empty sketch that overestimates counts by more than
+    /// `epsilon * total()` with probability at most `delta`.
+    ///
+    /// Both `epsilon` and `delta` must be in `(0, 1)`.
+    pub fn with_error(epsilon: f64, delta: f64) -> CountMinSketch<T> {
+        assert!(epsilon > 0.0 && epsilon < 1.0,
+                "epsilon {} is not in (0, 1)", epsilon);
+        assert!(delta > 0.0 && delta < 1.0,
+                "delta {} is not in (0, 1)", delta);
+        let width = (::std::f64::consts::E / epsilon).ceil() as usize;
+        let depth = (1.0 / delta).ln().ceil() as usize;
+        CountMinSketch::with_dimensions(width, depth.max(1))
+    }
+
+    /// Create an empty sketch with `depth` rows of `width` counters whose
+    /// hashes are seeded by `seed`.
+    pub fn with_seed(width: usize, depth: usize,
+                     seed: u64) -> CountMinSketch<T> {
+        assert!(width >= 1 && depth >= 1,
+                "width and depth must be at least 1");
+        CountMinSketch {
+            width,
+            depth,
+            seed,
+            conservative: false,
+            total: 0,
+            counters: vec![0; width * depth],
+            error: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets whether samples are added with conservative update.
+    pub fn set_conservative(&mut self, conservative: bool) {
+        self.conservative = conservative;
+    }
+
+    /// Add a sample to the sketch.
+    pub fn add(&mut self, v: T) {
+        let cells = self.cells(&v);
+        self.total += 1;
+        if self.conservative {
+            let min = cells.clone().map(|i| self.counters[i]).min().unwrap();
+            for i in cells {
+                if self.counters[i] == min {
+                    self.counters[i] += 1;
+                }
+            }
+        } else {
+            for i in cells {
+                self.counters[i] += 1;
+            }
+        }
+    }
+
+    /// Returns the estimated number of occurrences of `v` in the data.
+    ///
+    /// This is never less than the true count.
+    pub fn count(&self, v: &T) -> u64 {
+        self.cells(v).map(|i| self.counters[i]).min().unwrap()
+    }
+
+    /// Returns the number of samples added.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns true if no samples have been added.
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Returns the number of counters in each row.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the number of rows.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the error of the last merge through `Commute` that failed.
+    pub fn merge_error(&self) -> Option<&CountMinMergeError> {
+        self.error.as_ref()
+    }
+
+    /// Merges `v` into this sketch, unless the sketches don't have the same
+    /// dimensions and seed.
+    pub fn try_merge(&mut self, v: CountMinSketch<T>)
+                    -> Result<(), CountMinMergeError> {
+        if (self.width, self.depth) != (v.width, v.depth) {
+            return Err(CountMinMergeError::Dimensions((self.width, self.depth),
+                                              (v.width, v.depth)));
+        }
+        if self.seed != v.seed {
+            return Err(CountMinMergeError::Seed(self.seed, v.seed));
+        }
+        for (a, b) in self.counters.iter_mut().zip(v.counters) {
+            *a += b;
+        }
+        self.total += v.total;
+        Ok(())
+    }
+
+    /// Returns the index of the counter for `v` in each row, without
+    /// allocating.
+    fn cells(&self, v: &T) -> impl Iterator<Item=usize> + Clone {
+        let mut hasher = DefaultHasher::new();
+        self.seed.hash(&mut hasher);
+        v.hash(&mut hasher);
+        // Rows use the hashes `h1 + i * h2`, which are as good as
+        // independent ones (Kirsch and Mitzenmacher, 2006).
+        let h1 = hasher.finish();
+        let h2 = Rng::new(h1).next_u64() | 1;
+        let width = self.width;
+        (0..self.depth).map(move |i| {
+            let h = h1.wrapping_add((i as u64).wrapping_mul(h2));
+            i * width + (h % width as u64) as usize
+        })
+    }
+}
+
+impl<T: Hash> Commute for CountMinSketch<T> {
+    fn merge(&mut self, v: CountMinSketch<T>) {
+        if v.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = CountMinSketch { error: self.error.take(), ..v };
+            return;
+        }
+        if let Err(err) = self.try_merge(v) {
+            self.error = Some(err);
+        }
+    }
+}
+
+impl<T: Hash> Default for CountMinSketch<T> {
+    fn default() -> CountMinSketch<T> {
+        CountMinSketch::with_error(0.001, 0.01)
+    }
+}
+
+impl<T: Hash> FromIterator<T> for CountMinSketch<T> {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> CountMinSketch<T> {
+        let mut v = CountMinSketch::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: Hash> Extend<T> for CountMinSketch<T> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, Frequencies, merge_all};
+    use super::{CountMinSketch, CountMinMergeError};
+
+    // A long tail: value `i` occurs `1000 / (i + 1)` times.
+    fn zipf() -> Vec<u64> {
+        (0..1000u64).flat_map(|i| vec![i; 1000 / (i as usize + 1)])
+                    .collect()
+    }
+
+    #[test]
+    fn dimensions() {
+        let sketch = CountMinSketch::<u64>::new();
+        assert_eq!((sketch.width(), sketch.depth()), (2719, 5));
+        let sketch = CountMinSketch::<u64>::with_error(0.01, 0.001);
+        assert_eq!((sketch.width(), sketch.depth()), (272, 7));
+    }
+
+    #[test]
+    fn overestimates() {
+        let data = zipf();
+        let exact: Frequencies<u64> = data.iter().cloned().collect();
+        let mut plain = CountMinSketch::with_error(0.005, 0.01);
+        let mut conservative = CountMinSketch::with_error(0.005, 0.01);
+        conservative.set_conservative(true);
+        plain.extend(data.iter().cloned());
+        conservative.extend(data.iter().cloned());
+        assert_eq!(plain.total(), data.len() as u64);
+
+        let bound = (0.005 * data.len() as f64) as u64;
+        for v in 0..1100 {
+            let (count, p, c) = (exact.count(&v), plain.count(&v),
+                                 conservative.count(&v));
+            assert!(count <= c && c <= p);
+            assert!(p - count <= bound, "{}: {} vs {}", v, p, count);
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let data = zipf();
+        let sketches = data.chunks(997).map(|c| c.iter().cloned().collect());
+        let mut merged: CountMinSketch<u64> = merge_all(sketches).unwrap();
+        merged.merge(CountMinSketch::new());
+        assert!(merged.merge_error().is_none());
+        let whole: CountMinSketch<u64> = data.iter().cloned().collect();
+        assert_eq!(merged.total(), whole.total());
+        for v in 0..1000 {
+            assert_eq!(merged.count(&v), whole.count(&v));
+        }
+    }
+
+    #[test]
+    fn merge_mismatched() {
+        let mut a: CountMinSketch<u64> = vec![1, 2, 3].into_iter().collect();
+        let mut b = CountMinSketch::with_dimensions(100, 3);
+        b.add(1);
+        assert_eq!(a.try_merge(b.clone()),
+                   Err(CountMinMergeError::Dimensions((2719, 5), (100, 3))));
+        let c = CountMinSketch::with_seed(2719, 5, 7);
+        assert_eq!(a.try_merge(c), Err(CountMinMergeError::Seed(0, 7)));
+        assert_eq!(a.count(&1), 1);
+        assert_eq!(a.total(), 3);
+
+        // Empty sketches must match too.
+        let mut empty = CountMinSketch::with_dimensions(10, 2);
+        assert_eq!(empty.try_merge(b),
+                   Err(CountMinMergeError::Dimensions((10, 2), (100, 3))));
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn merge_mismatched_commute() {
+        let mut a: CountMinSketch<u64> = vec![1, 2, 3].into_iter().collect();
+        let mut b = CountMinSketch::with_dimensions(100, 3);
+        b.add(1);
+        a.merge(b);
+        assert_eq!(a.merge_error(),
+                   Some(&CountMinMergeError::Dimensions((2719, 5),
+                                                        (100, 3))));
+        assert_eq!(a.total(), 3);
+    }
+
+    #[test]
+    fn merge_empty() {
+        let mut a = CountMinSketch::with_dimensions(100, 3);
+        a.extend(vec![1u64, 2, 3]);
+        a.merge(CountMinSketch::default());
+        assert!(a.merge_error().is_none());
+        assert_eq!(a.total(), 3);
+
+        let mut empty = CountMinSketch::default();
+        empty.merge(a);
+        assert!(empty.merge_error().is_none());
+        assert_eq!((empty.width(), empty.depth()), (100, 3));
+        assert_eq!(empty.count(&1), 1);
+    }
+}
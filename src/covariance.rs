@@ -0,0 +1,475 @@
+use std::default::Default;
+use std::iter::{FromIterator, IntoIterator};
+
+use num::ToPrimitive;
+
+use Commute;
+
+/// Compute the covariance of a stream of pairs in constant space.
+///
+/// This is the population covariance.
+pub fn covariance<X, Y, I>(it: I) -> f64
+        where X: ToPrimitive, Y: ToPrimitive, I: Iterator<Item=(X, Y)> {
+    it.collect::<OnlineCovariance>().covariance()
+}
+
+/// Compute the Pearson correlation of a stream of pairs in constant space.
+pub fn correlation<X, Y, I>(it: I) -> f64
+        where X: ToPrimitive, Y: ToPrimitive, I: Iterator<Item=(X, Y)> {
+    it.collect::<OnlineCovariance>().correlation()
+}
+
+/// Online state for computing the relationship between two variables.
+///
+/// This tracks covariance, Pearson correlation and the least squares fit
+/// `y = slope * x + intercept` of a stream of `(x, y)` pairs.
+#[derive(Clone, Copy, Debug)]
+pub struct OnlineCovariance {
+    size: u64,
+    mean_x: f64,
+    mean_y: f64,
+    // Sums of squared differences from the mean of `x` and `y`.
+    m2_x: f64,
+    m2_y: f64,
+    // Sum of the products of the differences from the means.
+    comoment: f64,
+}
+
+impl OnlineCovariance {
+    /// Create initial state.
+    pub fn new() -> OnlineCovariance {
+        Default::default()
+    }
+
+    /// Add a new `(x, y)` pair.
+    pub fn add<X: ToPrimitive, Y: ToPrimitive>(&mut self, x: X, y: Y) {
+        let (x, y) = (x.to_f64().unwrap(), y.to_f64().unwrap());
+        self.size += 1;
+        let n = self.size as f64;
+        let dx = x - self.mean_x;
+        let dy = y - self.mean_y;
+        self.mean_x += dx / n;
+        self.mean_y += dy / n;
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+        self.comoment += dx * (y - self.mean_y);
+    }
+
+    /// Returns the number of pairs.
+    pub fn len(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Returns true if there are no pairs.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Return the mean of `x`.
+    pub fn mean_x(&self) -> f64 {
+        self.mean_x
+    }
+
+    /// Return the mean of `y`.
+    pub fn mean_y(&self) -> f64 {
+        self.mean_y
+    }
+
+    /// Return the population covariance.
+    pub fn covariance(&self) -> f64 {
+        if self.size == 0 { 0.0 } else { self.comoment / (self.size as f64) }
+    }
+
+    /// Return the sample covariance.
+    ///
+    /// This divides by `len - 1` instead of `len`. If there are fewer than
+    /// two pairs, then this is `NaN`.
+    pub fn sample_covariance(&self) -> f64 {
+        if self.size < 2 {
+            return f64::NAN;
+        }
+        self.comoment / ((self.size - 1) as f64)
+    }
+
+    /// Return the Pearson correlation coefficient.
+    ///
+    /// If either variable has a variance of `0`, then this is `NaN`.
+    pub fn correlation(&self) -> f64 {
+        self.comoment / (self.m2_x * self.m2_y).sqrt()
+    }
+
+    /// Return the slope of the least squares line fitting `y` from `x`.
+    ///
+    /// If `x` has a variance of `0`, then this is `NaN`.
+    pub fn slope(&self) -> f64 {
+        self.comoment / self.m2_x
+    }
+
+    /// Return the intercept of the least squares line fitting `y` from `x`.
+    pub fn intercept(&self) -> f64 {
+        self.mean_y - self.slope() * self.mean_x
+    }
+
+    /// Return the coefficient of determination of the least squares line.
+    ///
+    /// For simple linear regression, this is the squared correlation.
+    pub fn r_squared(&self) -> f64 {
+        let r = self.correlation();
+        r * r
+    }
+}
+
+impl Commute for OnlineCovariance {
+    fn merge(&mut self, v: OnlineCovariance) {
+        if v.size == 0 {
+            return;
+        }
+        if self.size == 0 {
+            *self = v;
+            return;
+        }
+        // See Pébay (2008), "Formulas for Robust, One-Pass Parallel
+        // Computation of Covariances and Arbitrary-Order Statistical
+        // Moments".
+        let (s1, s2) = (self.size as f64, v.size as f64);
+        let n = s1 + s2;
+        let dx = v.mean_x - self.mean_x;
+        let dy = v.mean_y - self.mean_y;
+        self.m2_x += v.m2_x + dx * dx * s1 * s2 / n;
+        self.m2_y += v.m2_y + dy * dy * s1 * s2 / n;
+        self.comoment += v.comoment + dx * dy * s1 * s2 / n;
+        self.mean_x += dx * s2 / n;
+        self.mean_y += dy * s2 / n;
+        self.size += v.size;
+    }
+}
+
+impl Default for OnlineCovariance {
+    fn default() -> OnlineCovariance {
+        OnlineCovariance {
+            size: 0,
+            mean_x: 0.0,
+            mean_y: 0.0,
+            m2_x: 0.0,
+            m2_y: 0.0,
+            comoment: 0.0,
+        }
+    }
+}
+
+impl<X: ToPrimitive, Y: ToPrimitive> FromIterator<(X, Y)>
+        for OnlineCovariance {
+    fn from_iter<I: IntoIterator<Item=(X, Y)>>(it: I) -> OnlineCovariance {
+        let mut v = OnlineCovariance::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<X: ToPrimitive, Y: ToPrimitive> Extend<(X, Y)> for OnlineCovariance {
+    fn extend<I: IntoIterator<Item=(X, Y)>>(&mut self, it: I) {
+        for (x, y) in it {
+            self.add(x, y);
+        }
+    }
+}
+
+/// Online state for computing the covariance matrix of `k` variables.
+///
+/// Each sample is a row of `k` values. The number of columns is fixed by
+/// `new`, or by the first row added when created with `Default`.
+#[derive(Clone, Debug)]
+pub struct OnlineCovarianceMatrix {
+    size: u64,
+    means: Vec<f64>,
+    // Row-major `k * k` matrix of the sums of the products of differences
+    // from the means. The diagonal is the `m2` of `OnlineStats`.
+    comoments: Vec<f64>,
+}
+
+impl OnlineCovarianceMatrix {
+    /// Create initial state for rows with `k` columns.
+    pub fn new(k: usize) -> OnlineCovarianceMatrix {
+        OnlineCovarianceMatrix {
+            size: 0,
+            means: vec![0.0; k],
+            comoments: vec![0.0; k * k],
+        }
+    }
+
+    /// Add a new row.
+    ///
+    /// Panics if the row does not have exactly `dimension()` columns.
+    pub fn add<T: ToPrimitive>(&mut self, row: &[T]) {
+        if self.size == 0 && self.means.is_empty() {
+            *self = OnlineCovarianceMatrix::new(row.len());
+        }
+        assert_eq!(row.len(), self.dimension());
+        let k = self.dimension();
+        let xs: Vec<f64> = row.iter().map(|x| x.to_f64().unwrap()).collect();
+        let deltas: Vec<f64> =
+            xs.iter().zip(&self.means).map(|(x, m)| x - m).collect();
+
+        // This is the Welford update of `OnlineStats::add`, applied to every
+        // pair of columns.
+        self.size += 1;
+        let n = self.size as f64;
+        for (mean, d) in self.means.iter_mut().zip(&deltas) {
+            *mean += d / n;
+        }
+        for (i, di) in deltas.iter().enumerate() {
+            for (j, xj) in xs.iter().enumerate() {
+                self.comoments[i * k + j] += di * (xj - self.means[j]);
+            }
+        }
+    }
+
+    /// Returns the number of columns.
+    pub fn dimension(&self) -> usize {
+        self.means.len()
+    }
+
+    /// Returns the number of rows.
+    pub fn len(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Returns true if there are no rows.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Return the mean of each column.
+    pub fn means(&self) -> &[f64] {
+        &self.means
+    }
+
+    /// Return the population variance of column `i`.
+    pub fn variance(&self, i: usize) -> f64 {
+        self.covariance(i, i)
+    }
+
+    /// Return the population covariance of columns `i` and `j`.
+    pub fn covariance(&self, i: usize, j: usize) -> f64 {
+        if self.size == 0 {
+            return 0.0;
+        }
+        self.comoment(i, j) / (self.size as f64)
+    }
+
+    /// Return the sample covariance of columns `i` and `j`.
+    ///
+    /// If there are fewer than two rows, then this is `NaN`.
+    pub fn sample_covariance(&self, i: usize, j: usize) -> f64 {
+        if self.size < 2 {
+            return f64::NAN;
+        }
+        self.comoment(i, j) / ((self.size - 1) as f64)
+    }
+
+    /// Return the Pearson correlation of columns `i` and `j`.
+    ///
+    /// If either column has a variance of `0`, then this is `NaN`.
+    pub fn correlation(&self, i: usize, j: usize) -> f64 {
+        self.comoment(i, j)
+        / (self.comoment(i, i) * self.comoment(j, j)).sqrt()
+    }
+
+    /// Return the population covariance matrix as a `Vec` of rows.
+    pub fn covariance_matrix(&self) -> Vec<Vec<f64>> {
+        self.matrix(|i, j| self.covariance(i, j))
+    }
+
+    /// Return the correlation matrix as a `Vec` of rows.
+    pub fn correlation_matrix(&self) -> Vec<Vec<f64>> {
+        self.matrix(|i, j| self.correlation(i, j))
+    }
+
+    fn comoment(&self, i: usize, j: usize) -> f64 {
+        self.comoments[i * self.dimension() + j]
+    }
+
+    fn matrix<F: Fn(usize, usize) -> f64>(&self, f: F) -> Vec<Vec<f64>> {
+        let k = self.dimension();
+        (0..k).map(|i| (0..k).map(|j| f(i, j)).collect()).collect()
+    }
+}
+
+impl Commute for OnlineCovarianceMatrix {
+    fn merge(&mut self, v: OnlineCovarianceMatrix) {
+        if v.size == 0 {
+            return;
+        }
+        if self.size == 0 {
+            *self = v;
+            return;
+        }
+        assert_eq!(self.dimension(), v.dimension());
+        // This is the merge of `OnlineStats`, applied to every pair of
+        // columns.
+        let k = self.dimension();
+        let (s1, s2) = (self.size as f64, v.size as f64);
+        let n = s1 + s2;
+        let deltas: Vec<f64> =
+            v.means.iter().zip(&self.means).map(|(m2, m1)| m2 - m1).collect();
+        for (i, di) in deltas.iter().enumerate() {
+            for (j, dj) in deltas.iter().enumerate() {
+                self.comoments[i * k + j] +=
+                    v.comoments[i * k + j] + di * dj * s1 * s2 / n;
+            }
+        }
+        for (mean, d) in self.means.iter_mut().zip(&deltas) {
+            *mean += d * s2 / n;
+        }
+        self.size += v.size;
+    }
+}
+
+impl Default for OnlineCovarianceMatrix {
+    fn default() -> OnlineCovarianceMatrix {
+        OnlineCovarianceMatrix::new(0)
+    }
+}
+
+impl<T: ToPrimitive> FromIterator<Vec<T>> for OnlineCovarianceMatrix {
+    fn from_iter<I: IntoIterator<Item=Vec<T>>>(it: I)
+                 -> OnlineCovarianceMatrix {
+        let mut v = OnlineCovarianceMatrix::default();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: ToPrimitive> Extend<Vec<T>> for OnlineCovarianceMatrix {
+    fn extend<I: IntoIterator<Item=Vec<T>>>(&mut self, it: I) {
+        for row in it {
+            self.add(&row);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use online::OnlineStats;
+    use testutil::close;
+    use super::{
+        OnlineCovariance, OnlineCovarianceMatrix, correlation, covariance,
+    };
+
+    #[test]
+    fn perfect_fit() {
+        let cov: OnlineCovariance =
+            (0..10).map(|x| (x, 3 * x + 2)).collect();
+        assert!(close(cov.slope(), 3.0));
+        assert!(close(cov.intercept(), 2.0));
+        assert!(close(cov.correlation(), 1.0));
+        assert!(close(cov.r_squared(), 1.0));
+    }
+
+    #[test]
+    fn two_pass() {
+        let pairs = [(1.0, 2.0), (2.0, 1.0), (4.0, 7.0), (5.0, 4.0),
+                     (7.0, 9.0), (8.0, 6.0)];
+        let n = pairs.len() as f64;
+        let mx = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+        let my = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+        let sxy = pairs.iter().map(|p| (p.0 - mx) * (p.1 - my)).sum::<f64>();
+        let sxx = pairs.iter().map(|p| (p.0 - mx) * (p.0 - mx)).sum::<f64>();
+        let syy = pairs.iter().map(|p| (p.1 - my) * (p.1 - my)).sum::<f64>();
+
+        let cov: OnlineCovariance = pairs.iter().cloned().collect();
+        assert!(close(cov.covariance(), sxy / n));
+        assert!(close(cov.sample_covariance(), sxy / (n - 1.0)));
+        assert!(close(cov.correlation(), sxy / (sxx * syy).sqrt()));
+        assert!(close(cov.slope(), sxy / sxx));
+        assert!(close(cov.intercept(), my - sxy / sxx * mx));
+        assert!(close(covariance(pairs.iter().cloned()), sxy / n));
+        assert!(close(correlation(pairs.iter().cloned()),
+                      sxy / (sxx * syy).sqrt()));
+    }
+
+    #[test]
+    fn merge() {
+        let pairs = [(1usize, 2usize), (2, 1), (4, 7), (5, 4), (7, 9),
+                     (8, 6), (3, 3)];
+        let expected: OnlineCovariance = pairs.iter().cloned().collect();
+        let parts = vec![
+            pairs[..2].iter().cloned().collect::<OnlineCovariance>(),
+            OnlineCovariance::new(),
+            pairs[2..].iter().cloned().collect::<OnlineCovariance>(),
+        ];
+        let got = merge_all(parts.into_iter()).unwrap();
+        assert_eq!(got.len(), expected.len());
+        assert!(close(got.covariance(), expected.covariance()));
+        assert!(close(got.correlation(), expected.correlation()));
+        assert!(close(got.slope(), expected.slope()));
+        assert!(close(got.intercept(), expected.intercept()));
+
+        let mut empty = OnlineCovariance::new();
+        empty.merge(OnlineCovariance::new());
+        assert!(empty.is_empty());
+        assert_eq!(empty.covariance(), 0.0);
+    }
+
+    fn rows() -> Vec<Vec<f64>> {
+        vec![
+            vec![1.0, 2.0, 10.0],
+            vec![2.0, 1.0, 8.0],
+            vec![4.0, 7.0, 7.5],
+            vec![5.0, 4.0, 3.0],
+            vec![7.0, 9.0, 1.0],
+            vec![8.0, 6.0, 0.5],
+        ]
+    }
+
+    #[test]
+    fn matrix_matches_pairs() {
+        let matrix: OnlineCovarianceMatrix = rows().into_iter().collect();
+        assert_eq!(matrix.dimension(), 3);
+        assert_eq!(matrix.len(), 6);
+        for i in 0..3 {
+            let col: Vec<f64> = rows().iter().map(|r| r[i]).collect();
+            let stats = OnlineStats::from_slice(&col);
+            assert!(close(matrix.means()[i], stats.mean()));
+            assert!(close(matrix.variance(i), stats.variance()));
+            for j in 0..3 {
+                let pairs: OnlineCovariance =
+                    rows().iter().map(|r| (r[i], r[j])).collect();
+                assert!(close(matrix.covariance(i, j), pairs.covariance()));
+                assert!(close(matrix.sample_covariance(i, j),
+                              pairs.sample_covariance()));
+                assert!(close(matrix.correlation(i, j), pairs.correlation()));
+            }
+        }
+        let corr = matrix.correlation_matrix();
+        assert!(close(corr[1][1], 1.0));
+        assert!(close(corr[0][2], corr[2][0]));
+        assert!(close(matrix.covariance_matrix()[0][1],
+                      matrix.covariance(0, 1)));
+    }
+
+    #[test]
+    fn matrix_merge() {
+        let expected: OnlineCovarianceMatrix = rows().into_iter().collect();
+        let mut got: OnlineCovarianceMatrix =
+            rows()[..2].iter().cloned().collect();
+        got.merge(OnlineCovarianceMatrix::new(3));
+        got.merge(rows()[2..].iter().cloned().collect());
+        assert_eq!(got.len(), expected.len());
+        for i in 0..3 {
+            assert!(close(got.means()[i], expected.means()[i]));
+            for j in 0..3 {
+                assert!(close(got.covariance(i, j),
+                              expected.covariance(i, j)));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn matrix_wrong_dimension() {
+        let mut matrix = OnlineCovarianceMatrix::new(2);
+        matrix.add(&[1, 2, 3]);
+    }
+}
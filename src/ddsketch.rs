@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+use std::default::Default;
+use std::iter::{FromIterator, IntoIterator};
+
+use num::ToPrimitive;
+
+use Commute;
+
+/// A commutative sketch for quantiles with a bounded relative error.
+///
+/// This is the DDSketch of Masson, Rim and Lee (2019). Samples are counted
+/// in logarithmically sized buckets, so every quantile is within a factor
+/// of `relative_accuracy()` of a sample at exactly that rank. Unlike rank
+/// error sketches, this stays accurate for data like latencies that span
+/// several orders of magnitude, even at extreme quantiles like `0.999`.
+///
+/// Positive and negative samples are kept in separate stores and zero has
+/// a bucket of its own. Memory can be capped with `with_max_buckets`, at
+/// the cost of accuracy for the quantiles in collapsed buckets.
+#[derive(Clone, Debug)]
+pub struct DDSketch {
+    alpha: f64,
+    gamma: f64,
+    max_buckets: usize,
+    collapse: Collapse,
+    count: u64,
+    min: f64,
+    max: f64,
+    zero: u64,
+    // Bucket `i` counts samples whose magnitude is in
+    // `(gamma^(i-1), gamma^i]`.
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+}
+
+/// Which buckets to fold together when a `DDSketch` exceeds its maximum
+/// number of buckets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Collapse {
+    /// Fold the buckets of the smallest magnitudes, keeping high quantiles
+    /// of positive data accurate.
+    #[default]
+    Lowest,
+    /// Fold the buckets of the largest magnitudes.
+    Highest,
+}
+
+impl DDSketch {
+    /// Create an empty sketch with the default relative accuracy of `0.01`
+    /// and no bound on the number of buckets.
+    pub fn new() -> DDSketch {
+        Default::default()
+    }
+
+    /// Create an empty sketch with the given relative accuracy and no
+    /// bound on the number of buckets.
+    ///
+    /// `alpha` must be in `(0, 1)`.
+    pub fn with_accuracy(alpha: f64) -> DDSketch {
+        assert!(alpha > 0.0 && alpha < 1.0,
+                "relative accuracy {} is not in (0, 1)", alpha);
+        DDSketch {
+            alpha,
+            gamma: (1.0 + alpha) / (1.0 - alpha),
+            max_buckets: usize::MAX,
+            collapse: Collapse::Lowest,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            zero: 0,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+        }
+    }
+
+    /// Create an empty sketch with the given relative accuracy that keeps
+    /// at most `max_buckets` buckets for each sign.
+    ///
+    /// When a store grows past `max_buckets`, buckets are folded together
+    /// as chosen by `collapse`. Quantiles that fall into the folded buckets
+    /// lose their accuracy guarantee.
+    pub fn with_max_buckets(alpha: f64, max_buckets: usize,
+                            collapse: Collapse) -> DDSketch {
+        assert!(max_buckets >= 1, "max_buckets must be at least 1");
+        DDSketch { max_buckets, collapse, ..DDSketch::with_accuracy(alpha) }
+    }
+
+    /// Add a sample to the sketch.
+    ///
+    /// `NaN` samples are ignored.
+    pub fn add<T: ToPrimitive>(&mut self, sample: T) {
+        let sample = sample.to_f64().unwrap();
+        if sample.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        if sample == 0.0 {
+            self.zero += 1;
+            return;
+        }
+        let i = self.index(sample.abs());
+        let store = if sample > 0.0 {
+            &mut self.positive
+        } else {
+            &mut self.negative
+        };
+        *store.entry(i).or_insert(0) += 1;
+        collapse(store, self.max_buckets, self.collapse);
+    }
+
+    /// Returns the number of samples in the sketch.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Returns true if there are no samples in the sketch.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the relative accuracy of this sketch.
+    pub fn relative_accuracy(&self) -> f64 {
+        self.alpha
+    }
+
+    /// Returns the approximate quantile `p` of the data, where `p` is in
+    /// `[0, 1]`.
+    ///
+    /// Unless its bucket was collapsed, the result is within a factor of
+    /// `relative_accuracy()` of the sample with rank `p * (len() - 1)`.
+    /// The quantiles `0` and `1` are always the exact minimum and maximum.
+    /// `None` is returned if and only if there is no data.
+    pub fn quantile(&self, p: f64) -> Option<f64> {
+        assert!((0.0..=1.0).contains(&p), "quantile {} is not in [0, 1]", p);
+        if self.count == 0 {
+            return None;
+        }
+        if p == 0.0 {
+            return Some(self.min);
+        }
+        if p == 1.0 {
+            return Some(self.max);
+        }
+        let rank = (p * (self.count - 1) as f64).floor() as u64;
+        let mut cumulative = 0;
+        let mut estimate = None;
+        for (&i, &n) in self.negative.iter().rev() {
+            cumulative += n;
+            if cumulative > rank {
+                estimate = Some(-self.value(i));
+                break;
+            }
+        }
+        if estimate.is_none() {
+            cumulative += self.zero;
+            if cumulative > rank {
+                estimate = Some(0.0);
+            }
+        }
+        if estimate.is_none() {
+            for (&i, &n) in &self.positive {
+                cumulative += n;
+                if cumulative > rank {
+                    estimate = Some(self.value(i));
+                    break;
+                }
+            }
+        }
+        // A bucket's value can lie beyond the samples it holds.
+        estimate.map(|v| v.max(self.min).min(self.max))
+    }
+
+    /// Returns the index of the bucket for the positive value `v`.
+    fn index(&self, v: f64) -> i32 {
+        (v.ln() / self.gamma.ln()).ceil() as i32
+    }
+
+    /// Returns the value that represents bucket `i`, which has a relative
+    /// error of at most `alpha` for everything in the bucket.
+    fn value(&self, i: i32) -> f64 {
+        2.0 * self.gamma.powi(i) / (self.gamma + 1.0)
+    }
+}
+
+/// Folds the extreme buckets of `store` together until it has at most
+/// `max_buckets` buckets.
+fn collapse(store: &mut BTreeMap<i32, u64>, max_buckets: usize,
+            how: Collapse) {
+    while store.len() > max_buckets {
+        match how {
+            Collapse::Lowest => {
+                let (_, n) = store.pop_first().unwrap();
+                *store.values_mut().next().unwrap() += n;
+            }
+            Collapse::Highest => {
+                let (_, n) = store.pop_last().unwrap();
+                *store.values_mut().next_back().unwrap() += n;
+            }
+        }
+    }
+}
+
+impl Commute for DDSketch {
+    fn merge(&mut self, v: DDSketch) {
+        assert!(self.gamma == v.gamma,
+                "cannot merge sketches with relative accuracy {} and {}",
+                self.alpha, v.alpha);
+        self.count += v.count;
+        self.min = self.min.min(v.min);
+        self.max = self.max.max(v.max);
+        self.zero += v.zero;
+        for (i, n) in v.positive {
+            *self.positive.entry(i).or_insert(0) += n;
+        }
+        for (i, n) in v.negative {
+            *self.negative.entry(i).or_insert(0) += n;
+        }
+        collapse(&mut self.positive, self.max_buckets, self.collapse);
+        collapse(&mut self.negative, self.max_buckets, self.collapse);
+    }
+}
+
+impl Default for DDSketch {
+    fn default() -> DDSketch {
+        DDSketch::with_accuracy(0.01)
+    }
+}
+
+impl<T: ToPrimitive> FromIterator<T> for DDSketch {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> DDSketch {
+        let mut v = DDSketch::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: ToPrimitive> Extend<T> for DDSketch {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use testutil::shuffle;
+    use super::{Collapse, DDSketch};
+
+    // Samples spanning nine orders of magnitude, in a scrambled order.
+    fn latencies(len: u64) -> Vec<f64> {
+        let mut xs: Vec<f64> = (0..len).map(|i| {
+            10f64.powf(-3.0 + 9.0 * i as f64 / len as f64)
+        }).collect();
+        shuffle(&mut xs);
+        xs
+    }
+
+    fn check_error(sketch: &DDSketch, sorted: &[f64]) {
+        let alpha = sketch.relative_accuracy();
+        for &p in &[0.0, 0.001, 0.01, 0.25, 0.5, 0.75, 0.99, 0.999, 1.0] {
+            let exact = sorted[(p * (sorted.len() - 1) as f64) as usize];
+            let got = sketch.quantile(p).unwrap();
+            assert!((got - exact).abs() <= alpha * exact.abs() + 1e-12,
+                    "p = {}: expected {}, got {}", p, exact, got);
+        }
+    }
+
+    #[test]
+    fn relative_error() {
+        let xs = latencies(100_000);
+        let sketch: DDSketch = xs.iter().cloned().collect();
+        assert_eq!(sketch.len(), 100_000);
+        assert!(sketch.positive.len() < 1_100);
+        let mut sorted = xs;
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        check_error(&sketch, &sorted);
+        assert_eq!(DDSketch::new().quantile(0.5), None);
+    }
+
+    #[test]
+    fn signs() {
+        let xs = vec![-1000, -10, -1, 0, 0, 1, 10, 100, 1000, 10000];
+        let sketch: DDSketch = xs.iter().cloned().collect();
+        assert_eq!(sketch.quantile(0.0), Some(-1000.0));
+        assert_eq!(sketch.quantile(1.0), Some(10000.0));
+        assert_eq!(sketch.quantile(0.4), Some(0.0));
+        let sorted: Vec<f64> = xs.iter().map(|&x| x as f64).collect();
+        check_error(&sketch, &sorted);
+    }
+
+    #[test]
+    fn collapse() {
+        let xs = latencies(100_000);
+        let mut sketch = DDSketch::with_max_buckets(0.01, 200,
+                                                    Collapse::Lowest);
+        sketch.extend(xs.iter().cloned());
+        assert_eq!(sketch.positive.len(), 200);
+        assert_eq!(sketch.len(), 100_000);
+        let mut sorted = xs;
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // The top buckets cover the last 2 orders of magnitude untouched.
+        for &p in &[0.9, 0.99, 0.999] {
+            let exact = sorted[(p * (sorted.len() - 1) as f64) as usize];
+            let got = sketch.quantile(p).unwrap();
+            assert!((got - exact).abs() <= 0.01 * exact);
+        }
+        // The bottom ones are folded into the lowest kept bucket.
+        assert!(sketch.quantile(0.01).unwrap() > 100.0);
+
+        let mut high = DDSketch::with_max_buckets(0.01, 200,
+                                                  Collapse::Highest);
+        high.extend(sorted.iter().cloned());
+        let exact = sorted[(0.01 * (sorted.len() - 1) as f64) as usize];
+        let got = high.quantile(0.01).unwrap();
+        assert!((got - exact).abs() <= 0.01 * exact);
+        assert!(high.quantile(0.999).unwrap() < 10.0);
+    }
+
+    #[test]
+    fn merge() {
+        let xs = latencies(100_000);
+        let sketches = xs.chunks(7_919).map(|c| c.iter().cloned().collect());
+        let mut merged: DDSketch = merge_all(sketches).unwrap();
+        merged.merge(DDSketch::new());
+        assert_eq!(merged.len(), 100_000);
+        let mut sorted = xs;
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        check_error(&merged, &sorted);
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_mismatched() {
+        let mut a = DDSketch::with_accuracy(0.01);
+        a.merge(DDSketch::with_accuracy(0.02));
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::iter::IntoIterator;
+
+use num::ToPrimitive;
+
+/// Online state for exponentially weighted mean, variance and standard
+/// deviation.
+///
+/// Every time a sample is added, the weight of all previous samples decays
+/// by a factor of `1 - alpha`. Samples can also be added with a timestamp,
+/// in which case the weights decay by `1 - alpha` per unit of time that has
+/// passed since the previous sample.
+///
+/// The statistics are normalized by the sum of the weights (this is what
+/// pandas calls `adjust=True`), so early estimates aren't biased towards
+/// `0`.
+#[derive(Clone, Copy)]
+pub struct Ewma {
+    alpha: f64,
+    size: u64,
+    weight: f64,
+    mean: f64,
+    // Weighted sum of squared differences from the mean.
+    m2: f64,
+    last_time: Option<f64>,
+}
+
+impl Ewma {
+    /// Create initial state with the given smoothing factor.
+    ///
+    /// `alpha` must be in `(0, 1]`. Larger values forget old samples more
+    /// quickly.
+    pub fn new(alpha: f64) -> Ewma {
+        assert!(alpha > 0.0 && alpha <= 1.0,
+                "alpha {} is not in (0, 1]", alpha);
+        Ewma {
+            alpha,
+            size: 0,
+            weight: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+            last_time: None,
+        }
+    }
+
+    /// Create initial state where the weight of a sample halves after
+    /// `half_life` more samples (or units of time) have been added.
+    pub fn with_half_life(half_life: f64) -> Ewma {
+        assert!(half_life > 0.0, "half life {} is not positive", half_life);
+        Ewma::new(1.0 - 0.5f64.powf(1.0 / half_life))
+    }
+
+    /// Return the smoothing factor.
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    /// Return the current exponentially weighted mean.
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Return the current exponentially weighted standard deviation.
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+
+    /// Return the current exponentially weighted variance.
+    pub fn variance(&self) -> f64 {
+        if self.weight == 0.0 { 0.0 } else { self.m2 / self.weight }
+    }
+
+    /// Add a new sample, decaying all previous samples by one step.
+    pub fn add<T: ToPrimitive>(&mut self, sample: T) {
+        self.update(sample.to_f64().unwrap(), 1.0);
+    }
+
+    /// Add a new sample observed at `time`.
+    ///
+    /// Previous samples decay according to the time elapsed since the
+    /// previous call to `add_at`, so that irregularly spaced samples are
+    /// weighted correctly. Samples with the same timestamp are weighted
+    /// equally. A sample with a timestamp earlier than one already seen is
+    /// treated as if it arrived at the latest time seen.
+    pub fn add_at<T: ToPrimitive>(&mut self, sample: T, time: f64) {
+        let elapsed = match self.last_time {
+            None => 0.0,
+            Some(last) => (time - last).max(0.0),
+        };
+        if self.last_time.map(|last| time > last).unwrap_or(true) {
+            self.last_time = Some(time);
+        }
+        self.update(sample.to_f64().unwrap(), elapsed);
+    }
+
+    fn update(&mut self, sample: f64, elapsed: f64) {
+        // Decaying every weight by the same factor leaves the mean alone
+        // and scales the sum of squares, after which this is the usual
+        // weighted update for a sample with a weight of `1`.
+        let decay = (1.0 - self.alpha).powf(elapsed);
+        self.weight *= decay;
+        self.m2 *= decay;
+
+        let prev = self.weight;
+        self.size += 1;
+        self.weight += 1.0;
+        let delta = sample - self.mean;
+        self.mean += delta / self.weight;
+        self.m2 += delta * delta * prev / self.weight;
+    }
+
+    /// Returns the number of data points.
+    pub fn len(&self) -> usize {
+        self.size as usize
+    }
+
+    /// Returns true if there are no data points.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+}
+
+impl fmt::Debug for Ewma {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.10} +/- {:.10}", self.mean(), self.stddev())
+    }
+}
+
+impl<T: ToPrimitive> Extend<T> for Ewma {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use testutil::close;
+    use super::Ewma;
+
+    #[test]
+    fn mean_variance() {
+        // With alpha = 0.5, the weights of [1, 2, 3] are [0.25, 0.5, 1].
+        let mut ewma = Ewma::new(0.5);
+        ewma.extend(vec![1usize, 2, 3]);
+        let mean = (0.25 + 1.0 + 3.0) / 1.75;
+        let var = (0.25 * (1.0 - mean) * (1.0 - mean)
+                   + 0.5 * (2.0 - mean) * (2.0 - mean)
+                   + (3.0 - mean) * (3.0 - mean)) / 1.75;
+        assert_eq!(ewma.len(), 3);
+        assert!(close(ewma.mean(), mean));
+        assert!(close(ewma.variance(), var));
+    }
+
+    #[test]
+    fn constant() {
+        let mut ewma = Ewma::new(0.1);
+        ewma.extend(vec![5.0; 100]);
+        assert!(close(ewma.mean(), 5.0));
+        assert!(close(ewma.variance(), 0.0));
+    }
+
+    #[test]
+    fn half_life() {
+        let mut ewma = Ewma::with_half_life(3.0);
+        ewma.add(8);
+        ewma.add(0);
+        ewma.add(0);
+        ewma.add(0);
+        // The first sample now has weight 0.5.
+        let total = 0.5 + 0.5f64.powf(2.0 / 3.0) + 0.5f64.powf(1.0 / 3.0)
+                    + 1.0;
+        assert!(close(ewma.mean(), 4.0 / total));
+    }
+
+    #[test]
+    fn time_decay() {
+        // After two units of time, the first sample has weight 0.8^2.
+        let mut timed = Ewma::new(0.2);
+        timed.add_at(1.0, 10.0);
+        timed.add_at(4.0, 12.0);
+        timed.add_at(6.0, 13.0);
+        let weights = [0.64 * 0.8, 0.8, 1.0];
+        let total: f64 = weights.iter().sum();
+        let mean = (weights[0] * 1.0 + weights[1] * 4.0 + weights[2] * 6.0)
+                   / total;
+        assert!(close(timed.mean(), mean));
+
+        // Samples at the same time are weighted equally.
+        let mut same = Ewma::new(0.9);
+        same.add_at(1.0, 5.0);
+        same.add_at(3.0, 5.0);
+        assert!(close(same.mean(), 2.0));
+        assert!(close(same.variance(), 1.0));
+    }
+}
@@ -1,20 +1,29 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::collections::hash_map::{HashMap, Entry};
 use std::fmt;
 use std::hash::Hash;
 use std::iter::{FromIterator, IntoIterator};
 use std::default::Default;
 
-use Commute;
+use {ChiSquare, Commute, ModeResult, extreme_counts};
 
 /// A commutative data structure for exact frequency counts.
+///
+/// Elements are also numbered in the order they are first seen, which is
+/// used to break ties deterministically. Merging numbers the new elements
+/// of the other table after the elements of this one.
 #[derive(Clone)]
 pub struct Frequencies<T> {
-    data: HashMap<T, u64>,
+    // Maps each element to its count and when it was first seen.
+    data: HashMap<T, (u64, u64)>,
+    next: u64,
+    total: u64,
 }
 
 impl<T: fmt::Debug + Eq + Hash> fmt::Debug for Frequencies<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self.data)
+        f.debug_map().entries(self.counts()).finish()
     }
 }
 
@@ -26,15 +35,19 @@ impl<T: Eq + Hash> Frequencies<T> {
 
     /// Add a sample to the frequency table.
     pub fn add(&mut self, v: T) {
+        self.total += 1;
         match self.data.entry(v) {
-            Entry::Vacant(count) => { count.insert(1); },
-            Entry::Occupied(mut count) => { *count.get_mut() += 1; },
+            Entry::Vacant(count) => {
+                count.insert((1, self.next));
+                self.next += 1;
+            }
+            Entry::Occupied(mut count) => { count.get_mut().0 += 1; },
         }
     }
 
     /// Return the number of occurrences of `v` in the data.
     pub fn count(&self, v: &T) -> u64 {
-        self.data.get(v).map(|&v| v).unwrap_or(0)
+        self.data.get(v).map(|&(n, _)| n).unwrap_or(0)
     }
 
     /// Return the cardinality (number of unique elements) in the data.
@@ -43,57 +56,293 @@ impl<T: Eq + Hash> Frequencies<T> {
     }
 
     /// Returns the mode if one exists.
+    ///
+    /// This is `None` if there is no data or if several values are tied.
     pub fn mode(&self) -> Option<&T> {
-        let counts = self.most_frequent();
-        if counts.is_empty() {
-            None
-        } else if counts.len() >= 2 && counts[0].1 == counts[1].1 {
-            None
-        } else {
-            Some(counts[0].0)
-        }
+        self.modes().unique()
+    }
+
+    /// Returns every value that occurs most often, in no particular order,
+    /// and how many times each occurs.
+    pub fn modes(&self) -> ModeResult<&T> {
+        extreme_counts(self.counts(), true)
+    }
+
+    /// Returns every value that occurs least often, in no particular order,
+    /// and how many times each occurs.
+    pub fn antimodes(&self) -> ModeResult<&T> {
+        extreme_counts(self.counts(), false)
     }
 
     /// Return a `Vec` of elements and their corresponding counts in
     /// descending order.
+    ///
+    /// Elements with the same count are in no particular order. See
+    /// `most_frequent_by_key` and `most_frequent_by_insertion` for a
+    /// deterministic order.
     pub fn most_frequent(&self) -> Vec<(&T, u64)> {
-        let mut counts: Vec<_> = self.data.iter()
-                                          .map(|(k, &v)| (k, v))
-                                          .collect();
-        counts.sort_by(|&(_, c1), &(_, c2)| c2.cmp(&c1));
+        let mut counts: Vec<_> = self.counts().collect();
+        counts.sort_by_key(|&(_, c)| ::std::cmp::Reverse(c));
         counts
     }
 
     /// Return a `Vec` of elements and their corresponding counts in
     /// ascending order.
+    ///
+    /// Elements with the same count are in no particular order.
     pub fn least_frequent(&self) -> Vec<(&T, u64)> {
-        let mut counts: Vec<_> = self.data.iter()
-                                          .map(|(k, &v)| (k, v))
-                                          .collect();
-        counts.sort_by(|&(_, c1), &(_, c2)| c1.cmp(&c2));
+        let mut counts: Vec<_> = self.counts().collect();
+        counts.sort_by_key(|&(_, c)| c);
         counts
     }
 
+    /// Like `most_frequent`, but elements with the same count are in the
+    /// order they were first seen.
+    pub fn most_frequent_by_insertion(&self) -> Vec<(&T, u64)> {
+        self.smallest(self.len(), most_by_insertion)
+    }
+
+    /// Like `least_frequent`, but elements with the same count are in the
+    /// order they were first seen.
+    pub fn least_frequent_by_insertion(&self) -> Vec<(&T, u64)> {
+        self.smallest(self.len(), least_by_insertion)
+    }
+
+    /// Returns the `k` most frequent elements and their counts in
+    /// descending order, breaking ties by the order they were first seen.
+    ///
+    /// This only sorts the `k` elements returned.
+    pub fn top_k(&self, k: usize) -> Vec<(&T, u64)> {
+        self.smallest(k, most_by_insertion)
+    }
+
+    /// Returns the `k` least frequent elements and their counts in
+    /// ascending order, breaking ties by the order they were first seen.
+    ///
+    /// This only sorts the `k` elements returned.
+    pub fn bottom_k(&self, k: usize) -> Vec<(&T, u64)> {
+        self.smallest(k, least_by_insertion)
+    }
+
+    /// Returns the `k` smallest entries according to `cmp`, in order.
+    fn smallest<F>(&self, k: usize, mut cmp: F) -> Vec<(&T, u64)>
+            where F: FnMut(&Tracked<T>, &Tracked<T>) -> Ordering {
+        if k == 0 {
+            return vec![];
+        }
+        let mut entries: Vec<Tracked<T>> = self.data.iter()
+                                               .map(|(v, &(n, i))| (v, n, i))
+                                               .collect();
+        if k < entries.len() {
+            entries.select_nth_unstable_by(k - 1, &mut cmp);
+            entries.truncate(k);
+        }
+        entries.sort_by(cmp);
+        entries.into_iter().map(|(v, n, _)| (v, n)).collect()
+    }
+
+    /// Returns each element with its count, in no particular order.
+    fn counts(&self) -> impl Iterator<Item=(&T, u64)> {
+        self.data.iter().map(|(k, &(n, _))| (k, n))
+    }
+
     /// Returns the cardinality of the data.
     pub fn len(&self) -> usize {
         self.data.len()
     }
+
+    /// Returns true if there is no frequency/cardinality data.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of samples in the data.
+    fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the fraction of the samples that are equal to `v`.
+    ///
+    /// This is `0` if there is no data.
+    pub fn relative_frequency(&self, v: &T) -> f64 {
+        let total = self.total();
+        if total == 0 { 0.0 } else { self.count(v) as f64 / total as f64 }
+    }
+
+    /// Returns each element with the fraction of the samples equal to it,
+    /// in no particular order.
+    pub fn proportions(&self) -> Vec<(&T, f64)> {
+        let total = self.total() as f64;
+        self.counts().map(|(k, n)| (k, n as f64 / total)).collect()
+    }
+
+    /// Returns the Shannon entropy of the data in bits.
+    ///
+    /// This is `0` if there is no data.
+    pub fn entropy(&self) -> f64 {
+        self.entropy_with_base(2.0)
+    }
+
+    /// Returns the Shannon entropy of the data, using logarithms in the
+    /// given base.
+    ///
+    /// `base` must be positive and not `1`. This is `0` if there is no data.
+    pub fn entropy_with_base(&self, base: f64) -> f64 {
+        assert!(base > 0.0 && base != 1.0,
+                "logarithm base {} must be positive and not 1", base);
+        self.natural_entropy() / base.ln()
+    }
+
+    fn natural_entropy(&self) -> f64 {
+        let total = self.total() as f64;
+        -self.data.values().map(|&(n, _)| {
+            let p = n as f64 / total;
+            p * p.ln()
+        }).sum::<f64>()
+    }
+
+    /// Returns the entropy of the data relative to the largest possible
+    /// entropy with as many unique elements, in `[0, 1]`.
+    ///
+    /// This is also known as Pielou's evenness. It is `0` if there are
+    /// fewer than two unique elements.
+    pub fn normalized_entropy(&self) -> f64 {
+        if self.len() < 2 {
+            return 0.0;
+        }
+        self.natural_entropy() / (self.len() as f64).ln()
+    }
+
+    /// Returns the probability that two samples drawn with replacement
+    /// are equal.
+    ///
+    /// This is Simpson's index, which is `0` if there is no data.
+    pub fn simpson_index(&self) -> f64 {
+        let total = self.total() as f64;
+        self.data.values().map(|&(n, _)| {
+            let p = n as f64 / total;
+            p * p
+        }).sum()
+    }
+
+    /// Returns the Gini impurity of the data: the probability that two
+    /// samples drawn with replacement are different.
+    ///
+    /// This is `0` if there is no data.
+    pub fn gini_impurity(&self) -> f64 {
+        if self.is_empty() { 0.0 } else { 1.0 - self.simpson_index() }
+    }
+
+    /// Returns the inverse of Simpson's index, which is the number of
+    /// equally common elements that would give the same index.
+    ///
+    /// This is `0` if there is no data.
+    pub fn simpson_diversity(&self) -> f64 {
+        if self.is_empty() { 0.0 } else { 1.0 / self.simpson_index() }
+    }
+
+    /// Returns the exponential of the Shannon entropy, which is the number
+    /// of equally common elements that would give the same entropy.
+    ///
+    /// This is `0` if there is no data.
+    pub fn shannon_diversity(&self) -> f64 {
+        if self.is_empty() { 0.0 } else { self.natural_entropy().exp() }
+    }
+
+    /// Returns Pearson's chi-square test of whether the data follows the
+    /// given distribution.
+    ///
+    /// `expected` pairs every possible element with its probability, and
+    /// is normalized to sum to `1`. Probabilities must not be negative, and
+    /// no element may be listed twice. Elements with probability `0` that
+    /// weren't observed are left out, along with their degree of freedom.
+    /// Observed elements that are missing from `expected`, or have
+    /// probability `0`, make the statistic infinite.
+    pub fn chi_square(&self, expected: &[(T, f64)]) -> ChiSquare {
+        let (cells, df) = self.fit(expected);
+        ChiSquare::pearson(cells.into_iter(), df)
+    }
+
+    /// Returns the G-test of whether the data follows the given
+    /// distribution.
+    ///
+    /// `expected` is interpreted as in `chi_square`.
+    pub fn g_test(&self, expected: &[(T, f64)]) -> ChiSquare {
+        let (cells, df) = self.fit(expected);
+        ChiSquare::g_test(cells.into_iter(), df)
+    }
+
+    /// Returns the observed and expected count of every element, with the
+    /// degrees of freedom.
+    fn fit(&self, expected: &[(T, f64)]) -> (Vec<(f64, f64)>, usize) {
+        assert!(expected.iter().all(|&(_, p)| p >= 0.0 && p.is_finite()),
+                "expected probabilities must be finite and non-negative");
+        let listed: HashSet<&T> = expected.iter().map(|(v, _)| v).collect();
+        assert!(listed.len() == expected.len(),
+                "expected elements must not be listed twice");
+        let total = self.total() as f64;
+        let sum: f64 = expected.iter().map(|&(_, p)| p).sum();
+        assert!(sum > 0.0, "expected probabilities must not all be zero");
+        // An impossible element that never occurred says nothing.
+        let mut cells: Vec<(f64, f64)> = expected.iter().map(|(v, p)| {
+            (self.count(v) as f64, total * p / sum)
+        }).filter(|&(o, e)| o > 0.0 || e > 0.0).collect();
+        let df = cells.len().saturating_sub(1);
+        cells.extend(self.counts()
+                         .filter(|&(k, _)| !listed.contains(k))
+                         .map(|(_, n)| (n as f64, 0.0)));
+        (cells, df)
+    }
+}
+
+impl<T: Eq + Hash + Ord> Frequencies<T> {
+    /// Like `most_frequent`, but elements with the same count are in
+    /// ascending order.
+    pub fn most_frequent_by_key(&self) -> Vec<(&T, u64)> {
+        self.smallest(self.len(), |a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)))
+    }
+
+    /// Like `least_frequent`, but elements with the same count are in
+    /// ascending order.
+    pub fn least_frequent_by_key(&self) -> Vec<(&T, u64)> {
+        self.smallest(self.len(), |a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)))
+    }
+}
+
+/// An element with its count and when it was first seen.
+type Tracked<'a, T> = (&'a T, u64, u64);
+
+fn most_by_insertion<T>(a: &Tracked<T>, b: &Tracked<T>) -> Ordering {
+    b.1.cmp(&a.1).then(a.2.cmp(&b.2))
+}
+
+fn least_by_insertion<T>(a: &Tracked<T>, b: &Tracked<T>) -> Ordering {
+    a.1.cmp(&b.1).then(a.2.cmp(&b.2))
 }
 
 impl<T: Eq + Hash> Commute for Frequencies<T> {
     fn merge(&mut self, v: Frequencies<T>) {
-        for (k, v2) in v.data.into_iter() {
+        // Keep the order in which `v` first saw its elements, after all of
+        // the elements seen so far.
+        let offset = self.next;
+        for (k, (n, i)) in v.data.into_iter() {
             match self.data.entry(k) {
-                Entry::Vacant(v1) => { v1.insert(v2); }
-                Entry::Occupied(mut v1) => { *v1.get_mut() += v2; }
+                Entry::Vacant(v1) => { v1.insert((n, offset + i)); }
+                Entry::Occupied(mut v1) => { v1.get_mut().0 += n; }
             }
         }
+        self.next += v.next;
+        self.total += v.total;
     }
 }
 
 impl<T: Eq + Hash> Default for Frequencies<T> {
     fn default() -> Frequencies<T> {
-        Frequencies { data: HashMap::with_capacity(100000) }
+        Frequencies {
+            data: HashMap::with_capacity(100000),
+            next: 0,
+            total: 0,
+        }
     }
 }
 
@@ -115,13 +364,189 @@ impl<T: Eq + Hash> Extend<T> for Frequencies<T> {
 
 #[cfg(test)]
 mod test {
+    use {Commute, ModeResult};
+    use testutil::close;
     use super::Frequencies;
 
     #[test]
     fn ranked() {
         let mut counts = Frequencies::new();
-        counts.extend(vec![1usize, 1, 2, 2, 2, 2, 2, 3, 4, 4, 4].into_iter());
+        counts.extend(vec![1usize, 1, 2, 2, 2, 2, 2, 3, 4, 4, 4]);
         assert_eq!(counts.most_frequent()[0], (&2, 5));
         assert_eq!(counts.least_frequent()[0], (&3, 1));
     }
+
+    #[test]
+    fn diversity() {
+        let counts: Frequencies<char> = "aabbbbcd".chars().collect();
+        // The proportions are 1/4, 1/2, 1/8 and 1/8.
+        assert_eq!(counts.relative_frequency(&'b'), 0.5);
+        assert_eq!(counts.relative_frequency(&'z'), 0.0);
+        let sum: f64 = counts.proportions().iter().map(|&(_, p)| p).sum();
+        assert!(close(sum, 1.0));
+        assert!(close(counts.entropy(), 1.75));
+        assert!(close(counts.entropy_with_base(4.0), 0.875));
+        assert!(close(counts.normalized_entropy(), 0.875));
+        assert!(close(counts.shannon_diversity(), 2f64.powf(1.75)));
+        assert!(close(counts.simpson_index(), 22.0 / 64.0));
+        assert!(close(counts.gini_impurity(), 42.0 / 64.0));
+        assert!(close(counts.simpson_diversity(), 64.0 / 22.0));
+    }
+
+    #[test]
+    fn goodness_of_fit() {
+        let mut rolls = Frequencies::new();
+        for (face, &n) in [22, 17, 20, 26, 22, 13].iter().enumerate() {
+            for _ in 0..n {
+                rolls.add(face + 1);
+            }
+        }
+        let fair: Vec<(usize, f64)> = (1..7).map(|f| (f, 1.0)).collect();
+        let chi = rolls.chi_square(&fair);
+        assert_eq!(chi.df, 5);
+        assert!(close(chi.statistic, 5.1));
+        assert!((chi.p_value - 0.4037984571042081).abs() < 1e-9);
+        let g = rolls.g_test(&fair);
+        assert!((g.statistic - 5.304238153761972).abs() < 1e-9);
+        assert!((g.p_value - 0.37988940939109517).abs() < 1e-9);
+
+        let mut impossible = fair.clone();
+        impossible.push((7, 0.0));
+        assert_eq!(rolls.chi_square(&impossible), chi);
+        assert_eq!(rolls.g_test(&impossible), g);
+
+        let unlisted = rolls.chi_square(&fair[..5]);
+        assert_eq!(unlisted.statistic, f64::INFINITY);
+        assert_eq!(unlisted.p_value, 0.0);
+    }
+
+    #[test]
+    fn diversity_degenerate() {
+        let even: Frequencies<u8> = (0..8).collect();
+        assert!(close(even.entropy(), 3.0));
+        assert!(close(even.normalized_entropy(), 1.0));
+        assert!(close(even.simpson_diversity(), 8.0));
+        assert!(close(even.shannon_diversity(), 8.0));
+
+        let one: Frequencies<u8> = vec![7; 5].into_iter().collect();
+        assert_eq!(one.entropy(), 0.0);
+        assert_eq!(one.normalized_entropy(), 0.0);
+        assert_eq!(one.gini_impurity(), 0.0);
+
+        let empty = Frequencies::<u8>::new();
+        assert_eq!(empty.entropy(), 0.0);
+        assert_eq!(empty.gini_impurity(), 0.0);
+        assert_eq!(empty.simpson_diversity(), 0.0);
+        assert_eq!(empty.shannon_diversity(), 0.0);
+        assert!(empty.proportions().is_empty());
+    }
+
+    #[test]
+    fn modes() {
+        let counts: Frequencies<char> = "abracadabra".chars().collect();
+        assert_eq!(counts.modes(), ModeResult::Unique(&'a', 5));
+        assert_eq!(counts.mode(), Some(&'a'));
+        match counts.antimodes() {
+            ModeResult::Multiple(mut values, 1) => {
+                values.sort();
+                assert_eq!(values, vec![&'c', &'d']);
+            }
+            other => panic!("unexpected antimodes {:?}", other),
+        }
+
+        let tied: Frequencies<u8> = vec![1, 2, 2, 1].into_iter().collect();
+        assert_eq!(tied.mode(), None);
+        assert_eq!(tied.modes().count(), 2);
+        assert_eq!(Frequencies::<u8>::new().modes(), ModeResult::Empty);
+        assert_eq!(Frequencies::<u8>::new().antimodes(), ModeResult::Empty);
+    }
+
+    #[test]
+    fn ordered_ties() {
+        let counts: Frequencies<char> = "mississippi".chars().collect();
+        assert_eq!(counts.most_frequent_by_key(),
+                   vec![(&'i', 4), (&'s', 4), (&'p', 2), (&'m', 1)]);
+        assert_eq!(counts.least_frequent_by_key(),
+                   vec![(&'m', 1), (&'p', 2), (&'i', 4), (&'s', 4)]);
+        assert_eq!(counts.most_frequent_by_insertion(),
+                   vec![(&'i', 4), (&'s', 4), (&'p', 2), (&'m', 1)]);
+
+        let counts: Frequencies<char> = "ssiimpp".chars().collect();
+        assert_eq!(counts.most_frequent_by_insertion(),
+                   vec![(&'s', 2), (&'i', 2), (&'p', 2), (&'m', 1)]);
+        assert_eq!(counts.least_frequent_by_insertion(),
+                   vec![(&'m', 1), (&'s', 2), (&'i', 2), (&'p', 2)]);
+    }
+
+    #[test]
+    fn top_and_bottom_k() {
+        let counts: Frequencies<char> = "ssiimpp".chars().collect();
+        assert_eq!(counts.top_k(2), vec![(&'s', 2), (&'i', 2)]);
+        assert_eq!(counts.bottom_k(2), vec![(&'m', 1), (&'s', 2)]);
+        assert_eq!(counts.top_k(10), counts.most_frequent_by_insertion());
+        assert!(counts.top_k(0).is_empty());
+        assert!(Frequencies::<u8>::new().bottom_k(3).is_empty());
+
+        // A larger table, checked against a full sort.
+        let data: Vec<u64> = (0..5_000u64).map(|i| (i * i) % 97).collect();
+        let counts: Frequencies<u64> = data.into_iter().collect();
+        let all = counts.most_frequent_by_insertion();
+        assert_eq!(counts.top_k(17), &all[..17]);
+        let mut least = counts.least_frequent_by_insertion();
+        least.truncate(17);
+        assert_eq!(counts.bottom_k(17), least);
+    }
+
+    #[test]
+    fn merge_keeps_insertion_order() {
+        let mut a: Frequencies<&str> = vec!["x", "y"].into_iter().collect();
+        let b: Frequencies<&str> =
+            vec!["w", "v", "y", "u"].into_iter().collect();
+        a.merge(b);
+        a.add("t");
+        assert_eq!(a.total(), 7);
+        let order: Vec<&str> = a.most_frequent_by_insertion()
+                                .into_iter().map(|(&v, _)| v).collect();
+        assert_eq!(order, vec!["y", "x", "w", "v", "u", "t"]);
+    }
+
+    #[test]
+    fn goodness_of_fit_zero_probability() {
+        let counts: Frequencies<u8> = vec![1, 1, 2].into_iter().collect();
+        let chi = counts.chi_square(&[(1, 0.5), (2, 0.5), (3, 0.0)]);
+        assert_eq!(chi.df, 1);
+        assert!(close(chi.statistic, 1.0 / 3.0));
+        assert!(!chi.p_value.is_nan());
+
+        let chi = counts.chi_square(&[(1, 0.5), (2, 0.0)]);
+        assert_eq!(chi.statistic, f64::INFINITY);
+    }
+
+    #[test]
+    #[should_panic]
+    fn goodness_of_fit_duplicate() {
+        let counts: Frequencies<u8> = vec![1, 2].into_iter().collect();
+        counts.g_test(&[(1, 0.5), (2, 0.25), (2, 0.25)]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn goodness_of_fit_negative_probability() {
+        let counts: Frequencies<u8> = vec![1, 2].into_iter().collect();
+        counts.chi_square(&[(1, 1.5), (2, -0.5)]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn entropy_base_one() {
+        let counts: Frequencies<u8> = vec![1, 2].into_iter().collect();
+        counts.entropy_with_base(1.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn entropy_base_negative() {
+        let counts: Frequencies<u8> = vec![1, 2].into_iter().collect();
+        counts.entropy_with_base(-2.0);
+    }
 }
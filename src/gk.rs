@@ -0,0 +1,240 @@
+use std::default::Default;
+use std::iter::{FromIterator, IntoIterator};
+
+use {Commute, Partial};
+
+/// A commutative summary for deterministic `epsilon`-approximate quantiles.
+///
+/// This is the summary of Greenwald and Khanna (2001). For any `phi`,
+/// `query(phi)` returns a sample whose rank is within `epsilon * len()` of
+/// `phi * len()`, and this guarantee doesn't depend on chance. The summary
+/// stores `O((1 / epsilon) log(epsilon * len()))` samples.
+///
+/// Like `Unsorted`, this works with types that do not define a total
+/// ordering like `f64`.
+#[derive(Clone)]
+pub struct GreenwaldKhanna<T> {
+    epsilon: f64,
+    count: u64,
+    // Sorted by value.
+    tuples: Vec<Tuple<T>>,
+}
+
+#[derive(Clone)]
+struct Tuple<T> {
+    v: Partial<T>,
+    // The difference between the smallest possible rank of `v` and that of
+    // the previous tuple.
+    g: u64,
+    // The difference between the largest and smallest possible rank of
+    // `v`.
+    delta: u64,
+}
+
+impl<T: PartialOrd> GreenwaldKhanna<T> {
+    /// Create an empty summary with the given error `epsilon`.
+    ///
+    /// `epsilon` must be in `(0, 1)`.
+    pub fn new(epsilon: f64) -> GreenwaldKhanna<T> {
+        assert!(epsilon > 0.0 && epsilon < 1.0,
+                "epsilon {} is not in (0, 1)", epsilon);
+        GreenwaldKhanna { epsilon, count: 0, tuples: vec![] }
+    }
+
+    /// Returns the error bound of this summary.
+    pub fn epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    /// Returns the number of samples inserted.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Returns true if no samples have been inserted.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Insert a new sample into the summary.
+    // `u64::is_multiple_of` would need Rust 1.87.
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn insert(&mut self, v: T) {
+        let v = Partial(v);
+        let i = self.tuples.partition_point(|t| t.v <= v);
+        // A new minimum or maximum has an exact rank. Otherwise, the rank
+        // is at most as uncertain as that of the next tuple.
+        let delta = if i == 0 || i == self.tuples.len() {
+            0
+        } else {
+            self.tuples[i].g + self.tuples[i].delta - 1
+        };
+        self.tuples.insert(i, Tuple { v, g: 1, delta });
+        self.count += 1;
+
+        let period = (1.0 / (2.0 * self.epsilon)).floor() as u64;
+        if self.count % period.max(1) == 0 {
+            self.compress();
+        }
+    }
+
+    /// Returns a sample whose rank is within `epsilon * len()` of
+    /// `phi * len()`, where `phi` is in `[0, 1]`.
+    ///
+    /// `None` is returned if and only if there is no data.
+    pub fn query(&self, phi: f64) -> Option<&T> {
+        assert!((0.0..=1.0).contains(&phi),
+                "quantile {} is not in [0, 1]", phi);
+        let n = self.count as f64;
+        let bound = (phi * n).ceil() + self.epsilon * n;
+        let mut rmin = 0;
+        for (i, t) in self.tuples.iter().enumerate() {
+            rmin += t.g;
+            if (rmin + t.delta) as f64 > bound {
+                return Some(&self.tuples[i.saturating_sub(1)].v.0);
+            }
+        }
+        self.tuples.last().map(|t| &t.v.0)
+    }
+
+    /// Merges adjacent tuples whose combined rank uncertainty stays within
+    /// `2 * epsilon * len()`.
+    fn compress(&mut self) {
+        let n = self.count as f64;
+        let threshold = (2.0 * self.epsilon * n).floor() as u64;
+        let len = self.tuples.len();
+        if len < 3 {
+            return;
+        }
+        // Going from right to left, fold a tuple into its right neighbour
+        // if that keeps the neighbour's uncertainty under the threshold.
+        // The first and last tuples are the exact minimum and maximum, so
+        // they are always kept.
+        let mut kept: Vec<Tuple<T>> = Vec::with_capacity(len);
+        let mut tuples = ::std::mem::take(&mut self.tuples).into_iter().rev();
+        kept.push(tuples.next().unwrap());
+        for (i, t) in tuples.enumerate() {
+            let last = kept.last_mut().unwrap();
+            if i + 2 < len && t.g + last.g + last.delta <= threshold {
+                last.g += t.g;
+            } else {
+                kept.push(t);
+            }
+        }
+        kept.reverse();
+        self.tuples = kept;
+    }
+}
+
+impl<T: PartialOrd> Commute for GreenwaldKhanna<T> {
+    /// Merges two summaries.
+    ///
+    /// The result keeps the error bound of the less accurate summary, but
+    /// may be larger than a summary built from all of the data at once.
+    fn merge(&mut self, v: GreenwaldKhanna<T>) {
+        let (a, b) = (::std::mem::take(&mut self.tuples), v.tuples);
+        let mut merged = Vec::with_capacity(a.len() + b.len());
+        let mut a = a.into_iter().peekable();
+        let mut b = b.into_iter().peekable();
+        // When a tuple from one summary is placed before a tuple from the
+        // other, the rank of that tuple among the other summary's samples
+        // is only known up to the uncertainty of the tuple that follows.
+        loop {
+            let from_a = match (a.peek(), b.peek()) {
+                (None, None) => break,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (Some(x), Some(y)) => x.v <= y.v,
+            };
+            let (mut t, next) = if from_a {
+                (a.next().unwrap(), b.peek())
+            } else {
+                (b.next().unwrap(), a.peek())
+            };
+            if let Some(next) = next {
+                t.delta += next.g + next.delta - 1;
+            }
+            merged.push(t);
+        }
+        self.tuples = merged;
+        self.count += v.count;
+        self.epsilon = self.epsilon.max(v.epsilon);
+        self.compress();
+    }
+}
+
+impl<T: PartialOrd> Default for GreenwaldKhanna<T> {
+    fn default() -> GreenwaldKhanna<T> {
+        GreenwaldKhanna::new(0.01)
+    }
+}
+
+impl<T: PartialOrd> FromIterator<T> for GreenwaldKhanna<T> {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> GreenwaldKhanna<T> {
+        let mut v = GreenwaldKhanna::default();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: PartialOrd> Extend<T> for GreenwaldKhanna<T> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.insert(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use testutil::shuffled;
+    use super::GreenwaldKhanna;
+
+    fn check_error(summary: &GreenwaldKhanna<u64>, n: u64) {
+        let allowed = summary.epsilon() * n as f64;
+        for i in 0..1001 {
+            let phi = i as f64 / 1000.0;
+            let rank = (*summary.query(phi).unwrap() + 1) as f64;
+            assert!((rank - phi * n as f64).abs() <= allowed + 1.0,
+                    "phi = {}: got rank {}", phi, rank);
+        }
+    }
+
+    #[test]
+    fn bounded() {
+        let n = 100_000;
+        let mut summary = GreenwaldKhanna::new(0.001);
+        summary.extend(shuffled(n));
+        assert_eq!(summary.len(), n as usize);
+        assert!(summary.tuples.len() < 5_000);
+        assert_eq!(summary.query(0.0), Some(&0));
+        assert_eq!(summary.query(1.0), Some(&(n - 1)));
+        check_error(&summary, n);
+    }
+
+    #[test]
+    fn merge() {
+        let n = 100_000;
+        let xs = shuffled(n);
+        let summaries = xs.chunks(9_973).map(|chunk| {
+            let mut summary = GreenwaldKhanna::new(0.001);
+            summary.extend(chunk.iter().cloned());
+            summary
+        });
+        let mut merged = merge_all(summaries).unwrap();
+        merged.merge(GreenwaldKhanna::new(0.001));
+        assert_eq!(merged.len(), n as usize);
+        check_error(&merged, n);
+    }
+
+    #[test]
+    fn floats() {
+        let summary: GreenwaldKhanna<f64> =
+            vec![3.5, 1.0, 2.25, 10.0, 7.0].into_iter().collect();
+        assert_eq!(summary.query(0.0), Some(&1.0));
+        assert_eq!(summary.query(0.5), Some(&3.5));
+        assert_eq!(summary.query(1.0), Some(&10.0));
+        assert_eq!(GreenwaldKhanna::<f64>::default().query(0.5), None);
+    }
+}
@@ -0,0 +1,321 @@
+use std::default::Default;
+use std::iter::{FromIterator, IntoIterator};
+
+use num::ToPrimitive;
+
+use {Commute, MinMax};
+
+/// A commutative histogram of integers with a fixed relative precision.
+///
+/// This follows the layout of Gil Tene's HdrHistogram: values from `0` up
+/// to a fixed maximum are counted in buckets whose width grows with the
+/// value, so that every recorded value is known to `significant_figures()`
+/// decimal digits. Recording is a constant time operation and memory only
+/// depends on the configuration, never on the number of samples.
+///
+/// The exact minimum, maximum and count are tracked with a `MinMax`.
+#[derive(Clone)]
+pub struct HdrHistogram {
+    max_value: u64,
+    sigfigs: u8,
+    unit_magnitude: u32,
+    sub_bucket_half_count_magnitude: u32,
+    sub_bucket_half_count: usize,
+    sub_bucket_mask: u64,
+    counts: Vec<u64>,
+    minmax: MinMax<u64>,
+}
+
+impl HdrHistogram {
+    /// Create an empty histogram that can record values from `0` up to
+    /// `max_value` with `sigfigs` significant decimal digits.
+    ///
+    /// `max_value` must be at least `2` and `sigfigs` at most `5`.
+    pub fn new(max_value: u64, sigfigs: u8) -> HdrHistogram {
+        assert!(max_value >= 2, "max_value {} must be at least 2", max_value);
+        assert!(sigfigs <= 5, "sigfigs {} must be at most 5", sigfigs);
+        let single_unit = 2 * 10u64.pow(sigfigs as u32);
+        // The number of bits needed to count every integer below
+        // `single_unit` exactly.
+        let count_magnitude = 64 - (single_unit - 1).leading_zeros();
+        let half_magnitude = count_magnitude.max(1) - 1;
+        let sub_bucket_count = 1u64 << (half_magnitude + 1);
+        let unit_magnitude = 0;
+
+        // Every bucket after the first doubles the range of values covered.
+        let mut smallest_untrackable = sub_bucket_count << unit_magnitude;
+        let mut bucket_count = 1;
+        while smallest_untrackable <= max_value {
+            if smallest_untrackable > u64::MAX / 2 {
+                bucket_count += 1;
+                break;
+            }
+            smallest_untrackable <<= 1;
+            bucket_count += 1;
+        }
+        let sub_bucket_half_count = (sub_bucket_count / 2) as usize;
+        HdrHistogram {
+            max_value,
+            sigfigs,
+            unit_magnitude,
+            sub_bucket_half_count_magnitude: half_magnitude,
+            sub_bucket_half_count,
+            sub_bucket_mask: (sub_bucket_count - 1) << unit_magnitude,
+            counts: vec![0; (bucket_count + 1) * sub_bucket_half_count],
+            minmax: MinMax::new(),
+        }
+    }
+
+    /// Add a sample to the histogram.
+    ///
+    /// Fractional samples are truncated toward zero. Samples outside of
+    /// `[0, max_value()]` are clamped to the nearest end of that range and
+    /// `NaN` samples are ignored.
+    pub fn add<T: ToPrimitive>(&mut self, sample: T) {
+        let v = match sample.to_u64() {
+            Some(v) => v.min(self.max_value),
+            None => {
+                let v = sample.to_f64().unwrap();
+                if v.is_nan() {
+                    return;
+                }
+                v.max(0.0).min(self.max_value as f64) as u64
+            }
+        };
+        let i = self.index(v);
+        self.counts[i] += 1;
+        self.minmax.add(v);
+    }
+
+    /// Returns the number of samples in the histogram.
+    pub fn len(&self) -> usize {
+        self.minmax.len()
+    }
+
+    /// Returns true if there are no samples in the histogram.
+    pub fn is_empty(&self) -> bool {
+        self.minmax.is_empty()
+    }
+
+    /// Returns the exact minimum of the samples.
+    pub fn min(&self) -> Option<u64> {
+        self.minmax.min().cloned()
+    }
+
+    /// Returns the exact maximum of the samples.
+    pub fn max(&self) -> Option<u64> {
+        self.minmax.max().cloned()
+    }
+
+    /// Returns the largest value this histogram can record.
+    pub fn max_value(&self) -> u64 {
+        self.max_value
+    }
+
+    /// Returns the number of significant decimal digits of every value.
+    pub fn significant_figures(&self) -> u8 {
+        self.sigfigs
+    }
+
+    /// Returns the quantile `p` of the data, where `p` is in `[0, 1]`.
+    ///
+    /// This is the largest value equivalent to the sample of rank
+    /// `p * len()`, which is accurate to `significant_figures()` digits.
+    /// `None` is returned if and only if there is no data.
+    pub fn quantile(&self, p: f64) -> Option<u64> {
+        assert!((0.0..=1.0).contains(&p), "quantile {} is not in [0, 1]", p);
+        let (min, max) = match (self.min(), self.max()) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return None,
+        };
+        let rank = ((p * self.len() as f64).round() as u64).max(1);
+        let mut cumulative = 0;
+        for (i, &n) in self.counts.iter().enumerate() {
+            cumulative += n;
+            if cumulative >= rank {
+                let v = self.highest_equivalent(self.value_at(i));
+                return Some(v.max(min).min(max));
+            }
+        }
+        Some(max)
+    }
+
+    /// Returns the percentile `pct` of the data, where `pct` is in
+    /// `[0, 100]`.
+    pub fn percentile(&self, pct: f64) -> Option<u64> {
+        self.quantile(pct / 100.0)
+    }
+
+    /// Returns an iterator over the recorded values in ascending order.
+    ///
+    /// Each item is the largest value equivalent to a recorded one, along
+    /// with the number of samples recorded as equivalent to it.
+    pub fn iter_recorded(&self) -> Recorded<'_> {
+        Recorded { hist: self, index: 0 }
+    }
+
+    fn bucket_index(&self, v: u64) -> u32 {
+        let base = 64 - self.unit_magnitude
+                   - self.sub_bucket_half_count_magnitude - 1;
+        base - (v | self.sub_bucket_mask).leading_zeros()
+    }
+
+    fn index(&self, v: u64) -> usize {
+        let bucket = self.bucket_index(v);
+        let sub_bucket = (v >> (bucket + self.unit_magnitude)) as usize;
+        ((bucket as usize + 1) << self.sub_bucket_half_count_magnitude)
+            + sub_bucket - self.sub_bucket_half_count
+    }
+
+    /// Returns the smallest value counted at index `i`.
+    fn value_at(&self, i: usize) -> u64 {
+        let half_magnitude = self.sub_bucket_half_count_magnitude;
+        let mut bucket = (i >> half_magnitude) as i64 - 1;
+        let mut sub_bucket = (i & (self.sub_bucket_half_count - 1))
+                             + self.sub_bucket_half_count;
+        if bucket < 0 {
+            sub_bucket -= self.sub_bucket_half_count;
+            bucket = 0;
+        }
+        (sub_bucket as u64) << (bucket as u32 + self.unit_magnitude)
+    }
+
+    /// Returns the largest value counted together with `v`.
+    fn highest_equivalent(&self, v: u64) -> u64 {
+        let bucket = self.bucket_index(v);
+        let sub_bucket = v >> (bucket + self.unit_magnitude);
+        let lowest = sub_bucket << (bucket + self.unit_magnitude);
+        let size = 1u64 << (bucket + self.unit_magnitude);
+        lowest.saturating_add(size - 1)
+    }
+}
+
+/// An iterator over the recorded values of an `HdrHistogram`.
+pub struct Recorded<'a> {
+    hist: &'a HdrHistogram,
+    index: usize,
+}
+
+impl<'a> Iterator for Recorded<'a> {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<(u64, u64)> {
+        let counts = &self.hist.counts;
+        while self.index < counts.len() {
+            let i = self.index;
+            self.index += 1;
+            if counts[i] > 0 {
+                let v = self.hist.highest_equivalent(self.hist.value_at(i));
+                return Some((v, counts[i]));
+            }
+        }
+        None
+    }
+}
+
+impl Commute for HdrHistogram {
+    fn merge(&mut self, v: HdrHistogram) {
+        assert!(self.max_value == v.max_value && self.sigfigs == v.sigfigs,
+                "cannot merge histograms with different configurations");
+        for (a, b) in self.counts.iter_mut().zip(v.counts) {
+            *a += b;
+        }
+        self.minmax.merge(v.minmax);
+    }
+}
+
+impl Default for HdrHistogram {
+    /// Create a histogram for up to an hour in nanoseconds with `3`
+    /// significant digits.
+    fn default() -> HdrHistogram {
+        HdrHistogram::new(3_600_000_000_000, 3)
+    }
+}
+
+impl<T: ToPrimitive> FromIterator<T> for HdrHistogram {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> HdrHistogram {
+        let mut v = HdrHistogram::default();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: ToPrimitive> Extend<T> for HdrHistogram {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use testutil::shuffled;
+    use super::HdrHistogram;
+
+    #[test]
+    fn exact_small_values() {
+        let hist: HdrHistogram = vec![1, 2, 2, 3, 4000].into_iter().collect();
+        assert_eq!(hist.len(), 5);
+        assert_eq!(hist.min(), Some(1));
+        assert_eq!(hist.max(), Some(4000));
+        assert_eq!(hist.percentile(0.0), Some(1));
+        assert_eq!(hist.percentile(50.0), Some(2));
+        assert_eq!(hist.percentile(100.0), Some(4000));
+        assert_eq!(hist.iter_recorded().collect::<Vec<_>>(),
+                   vec![(1, 1), (2, 2), (3, 1), (4001, 1)]);
+        assert_eq!(HdrHistogram::default().quantile(0.5), None);
+    }
+
+    #[test]
+    fn precision() {
+        let mut hist = HdrHistogram::new(1_000_000_000, 3);
+        let xs: Vec<u64> = shuffled(100_000).into_iter()
+                                            .map(|x| x * 9_973)
+                                            .collect();
+        hist.extend(xs.iter().cloned());
+        for &pct in &[1.0, 25.0, 50.0, 90.0, 99.0, 99.9] {
+            let exact = (pct / 100.0 * 100_000.0) as u64 * 9_973;
+            let got = hist.percentile(pct).unwrap();
+            assert!((got as f64 - exact as f64).abs() / exact as f64 <= 1e-3,
+                    "pct = {}: expected {}, got {}", pct, exact, got);
+        }
+        let recorded: u64 = hist.iter_recorded().map(|(_, n)| n).sum();
+        assert_eq!(recorded, 100_000);
+    }
+
+    #[test]
+    fn clamped() {
+        let mut hist = HdrHistogram::new(1000, 2);
+        hist.add(-5);
+        hist.add(2.6);
+        hist.add(1_000_000);
+        hist.add(f64::NAN);
+        assert_eq!(hist.len(), 3);
+        assert_eq!(hist.min(), Some(0));
+        assert_eq!(hist.percentile(50.0), Some(2));
+        assert_eq!(hist.max(), Some(1000));
+    }
+
+    #[test]
+    fn merge() {
+        let xs = shuffled(100_000);
+        let hists = xs.chunks(7_919).map(|c| c.iter().cloned().collect());
+        let mut merged: HdrHistogram = merge_all(hists).unwrap();
+        merged.merge(HdrHistogram::default());
+        let whole: HdrHistogram = xs.into_iter().collect();
+        assert_eq!(merged.len(), 100_000);
+        assert_eq!(merged.min(), Some(0));
+        assert_eq!(merged.max(), Some(99_999));
+        assert_eq!(merged.iter_recorded().collect::<Vec<_>>(),
+                   whole.iter_recorded().collect::<Vec<_>>());
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_mismatched() {
+        let mut a = HdrHistogram::new(1000, 2);
+        a.merge(HdrHistogram::new(1000, 3));
+    }
+}
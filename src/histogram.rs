@@ -0,0 +1,421 @@
+use std::default::Default;
+use std::iter::IntoIterator;
+
+use num::ToPrimitive;
+
+use {Commute, OnlineStats, Unsorted};
+
+/// The most bins `Histogram::from_unsorted` will pick.
+const MAX_BINS: usize = 10_000;
+
+/// A commutative histogram over fixed bins.
+///
+/// Bin `i` counts the samples in `[edges[i], edges[i + 1])`, except that
+/// the last bin also includes its upper edge. Samples below the first edge
+/// or above the last are counted as underflow and overflow.
+///
+/// Histograms only merge if they have the same edges. The default
+/// histogram has no bins and is only useful as the identity of `merge`;
+/// samples added to it are all counted as overflow.
+#[derive(Clone, Debug, Default)]
+pub struct Histogram {
+    edges: Vec<f64>,
+    counts: Vec<u64>,
+    underflow: u64,
+    overflow: u64,
+}
+
+impl Histogram {
+    /// Create an empty histogram with the given bin edges.
+    ///
+    /// There must be at least two edges and they must be finite and
+    /// strictly increasing.
+    pub fn with_edges(edges: Vec<f64>) -> Histogram {
+        assert!(edges.len() >= 2, "a histogram needs at least two edges");
+        assert!(edges.iter().all(|e| e.is_finite()),
+                "histogram edges must be finite");
+        assert!(edges.windows(2).all(|w| w[0] < w[1]),
+                "histogram edges must be strictly increasing");
+        Histogram {
+            counts: vec![0; edges.len() - 1],
+            edges,
+            underflow: 0,
+            overflow: 0,
+        }
+    }
+
+    /// Create an empty histogram with `bins` bins of equal width between
+    /// `min` and `max`.
+    pub fn equal_width(min: f64, max: f64, bins: usize) -> Histogram {
+        assert!(bins >= 1, "a histogram needs at least one bin");
+        let width = (max - min) / bins as f64;
+        let mut edges: Vec<f64> =
+            (0..bins).map(|i| min + i as f64 * width).collect();
+        edges.push(max);
+        Histogram::with_edges(edges)
+    }
+
+    /// Create an empty histogram with `bins` bins between `min` and `max`
+    /// whose edges are spaced evenly on a logarithmic scale.
+    ///
+    /// `min` must be positive.
+    pub fn log_spaced(min: f64, max: f64, bins: usize) -> Histogram {
+        assert!(bins >= 1, "a histogram needs at least one bin");
+        assert!(min > 0.0, "log-spaced bins need a positive minimum");
+        let ratio = (max / min).ln() / bins as f64;
+        let mut edges: Vec<f64> =
+            (0..bins).map(|i| min * (i as f64 * ratio).exp()).collect();
+        edges.push(max);
+        Histogram::with_edges(edges)
+    }
+
+    /// Add a sample to the histogram.
+    ///
+    /// `NaN` samples are ignored.
+    pub fn add<T: ToPrimitive>(&mut self, sample: T) {
+        let x = sample.to_f64().unwrap();
+        if x.is_nan() {
+            return;
+        }
+        match self.bin(x) {
+            Ok(i) => self.counts[i] += 1,
+            Err(true) => self.underflow += 1,
+            Err(false) => self.overflow += 1,
+        }
+    }
+
+    /// Returns the index of the bin `x` belongs to, or whether it is below
+    /// the first edge if it belongs to none.
+    fn bin(&self, x: f64) -> Result<usize, bool> {
+        match (self.edges.first(), self.edges.last()) {
+            (Some(&first), Some(&last)) => {
+                if x < first {
+                    Err(true)
+                } else if x > last {
+                    Err(false)
+                } else if x == last {
+                    Ok(self.counts.len() - 1)
+                } else {
+                    Ok(self.edges.partition_point(|&e| e <= x) - 1)
+                }
+            }
+            _ => Err(false),
+        }
+    }
+
+    /// Returns the edges of the bins.
+    pub fn edges(&self) -> &[f64] {
+        &self.edges
+    }
+
+    /// Returns the number of samples in each bin.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// Returns the number of samples below the first edge.
+    pub fn underflow(&self) -> u64 {
+        self.underflow
+    }
+
+    /// Returns the number of samples above the last edge.
+    pub fn overflow(&self) -> u64 {
+        self.overflow
+    }
+
+    /// Returns the number of samples, including underflow and overflow.
+    pub fn len(&self) -> usize {
+        (self.in_range() + self.underflow + self.overflow) as usize
+    }
+
+    /// Returns true if no samples have been added.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn in_range(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the estimated probability density in each bin.
+    ///
+    /// This is normalized so that the area of all bins together is `1`.
+    /// Underflow and overflow are not included. Every density is `0` if
+    /// there are no samples within the edges.
+    pub fn density(&self) -> Vec<f64> {
+        let total = self.in_range() as f64;
+        self.counts.iter().zip(self.edges.windows(2)).map(|(&n, w)| {
+            if total == 0.0 { 0.0 } else { n as f64 / total / (w[1] - w[0]) }
+        }).collect()
+    }
+
+    /// Returns the number of samples at most the upper edge of each bin.
+    ///
+    /// Underflow is included, so the last count is `len() - overflow()`.
+    pub fn cumulative(&self) -> Vec<u64> {
+        let mut sum = self.underflow;
+        self.counts.iter().map(|&n| { sum += n; sum }).collect()
+    }
+}
+
+/// A rule for choosing the number of bins of a histogram from the data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinRule {
+    /// `log2(n) + 1` bins, which assumes roughly normal data.
+    Sturges,
+    /// Bins of width `3.49 * stddev / n^(1/3)`.
+    Scott,
+    /// Bins of width `2 * IQR / n^(1/3)`, which is robust to outliers.
+    ///
+    /// If the IQR is zero, as when more than half of the samples are
+    /// equal, this falls back to Sturges' rule.
+    FreedmanDiaconis,
+    /// `sqrt(n)` bins.
+    Sqrt,
+    /// Sturges' rule with extra bins for skewed data.
+    Doane,
+}
+
+impl BinRule {
+    /// Returns the number of bins this rule picks for `data`, which must
+    /// not be empty.
+    ///
+    /// This is at most the number of samples, and at most `MAX_BINS`.
+    fn bins<T>(self, data: &mut Unsorted<T>) -> usize
+        where T: PartialOrd + ToPrimitive {
+        let n = data.len() as f64;
+        let qs = data.quantiles(&[0.0, 0.25, 0.75, 1.0]).unwrap();
+        let range = qs[3] - qs[0];
+        let sturges = n.log2().ceil() + 1.0;
+        let width = |h: f64| {
+            if h > 0.0 { (range / h).ceil() } else { sturges }
+        };
+        let bins = match self {
+            BinRule::Sturges => sturges,
+            BinRule::Sqrt => n.sqrt().ceil(),
+            BinRule::Scott => {
+                let stats = OnlineStats::from_slice(data.as_slice());
+                width(3.49 * stats.stddev() / n.cbrt())
+            }
+            BinRule::FreedmanDiaconis => {
+                width(2.0 * (qs[2] - qs[1]) / n.cbrt())
+            }
+            BinRule::Doane => {
+                let stats = OnlineStats::from_slice(data.as_slice());
+                let g1 = stats.skewness();
+                let sigma = (6.0 * (n - 2.0)
+                             / ((n + 1.0) * (n + 3.0))).sqrt();
+                let extra = if n > 2.0 && g1.is_finite() {
+                    (1.0 + g1.abs() / sigma).log2()
+                } else {
+                    0.0
+                };
+                (1.0 + n.log2() + extra).ceil()
+            }
+        };
+        let max = data.len().min(MAX_BINS);
+        if bins.is_finite() { (bins as usize).clamp(1, max) } else { max }
+    }
+}
+
+impl Histogram {
+    /// Create a histogram of `data` with equal width bins between its
+    /// minimum and maximum, as many as `rule` picks.
+    ///
+    /// The number of bins is limited so that the edges stay distinct
+    /// floating point numbers. If every sample is the same, there is a
+    /// single bin of width `1` around it. `None` is returned if and only if
+    /// there is no data.
+    pub fn from_unsorted<T>(data: &mut Unsorted<T>, rule: BinRule)
+                           -> Option<Histogram>
+        where T: PartialOrd + ToPrimitive {
+        if data.is_empty() {
+            return None;
+        }
+        let bins = rule.bins(data);
+        let qs = data.quantiles(&[0.0, 1.0]).unwrap();
+        let (min, max) = (qs[0], qs[1]);
+        let mut hist = if min == max {
+            Histogram::equal_width(min - 0.5, max + 0.5, 1)
+        } else {
+            // Bins at least two ulps wide keep the rounded edges strictly
+            // increasing.
+            let ulp = min.abs().max(max.abs()) * f64::EPSILON;
+            let limit = ((max - min) / (2.0 * ulp)).floor().max(1.0);
+            Histogram::equal_width(min, max, bins.min(limit as usize))
+        };
+        for x in data.as_slice() {
+            hist.add(x.to_f64().unwrap());
+        }
+        Some(hist)
+    }
+}
+
+impl Commute for Histogram {
+    fn merge(&mut self, v: Histogram) {
+        if v.edges.is_empty() {
+            self.overflow += v.overflow;
+            return;
+        }
+        if self.edges.is_empty() {
+            let overflow = self.overflow;
+            *self = v;
+            self.overflow += overflow;
+            return;
+        }
+        assert!(self.edges == v.edges,
+                "cannot merge histograms with different edges");
+        for (a, b) in self.counts.iter_mut().zip(v.counts) {
+            *a += b;
+        }
+        self.underflow += v.underflow;
+        self.overflow += v.overflow;
+    }
+}
+
+impl<T: ToPrimitive> Extend<T> for Histogram {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, Unsorted, merge_all};
+    use testutil::close;
+    use super::{BinRule, Histogram};
+
+    #[test]
+    fn equal_width() {
+        let mut hist = Histogram::equal_width(0.0, 10.0, 5);
+        assert_eq!(hist.edges(), &[0.0, 2.0, 4.0, 6.0, 8.0, 10.0]);
+        hist.extend(vec![-1.0, 0.0, 1.9, 2.0, 5.0, 9.9, 10.0, 10.5]);
+        hist.add(f64::NAN);
+        assert_eq!(hist.counts(), &[2, 1, 1, 0, 2]);
+        assert_eq!(hist.underflow(), 1);
+        assert_eq!(hist.overflow(), 1);
+        assert_eq!(hist.len(), 8);
+        assert_eq!(hist.cumulative(), vec![3, 4, 5, 5, 7]);
+    }
+
+    #[test]
+    fn edges() {
+        let mut hist = Histogram::with_edges(vec![0.0, 1.0, 10.0]);
+        hist.extend(vec![0.5, 2.0, 3.0, 9.0]);
+        assert_eq!(hist.counts(), &[1, 3]);
+        let density = hist.density();
+        assert!(close(density[0], 0.25));
+        assert!(close(density[1], 0.75 / 9.0));
+        let area: f64 = density.iter().zip(&[1.0, 9.0])
+                               .map(|(d, w)| d * w).sum();
+        assert!(close(area, 1.0));
+    }
+
+    #[test]
+    fn log_spaced() {
+        let mut hist = Histogram::log_spaced(1.0, 1000.0, 3);
+        let edges = hist.edges().to_vec();
+        assert_eq!(edges.len(), 4);
+        assert!(close(edges[1], 10.0) && close(edges[2], 100.0));
+        hist.extend(vec![1, 5, 50, 500, 1000]);
+        assert_eq!(hist.counts(), &[2, 1, 2]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn unordered_edges() {
+        Histogram::with_edges(vec![0.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn merge() {
+        let shards = (0..4).map(|i| {
+            let mut hist = Histogram::equal_width(0.0, 4.0, 4);
+            hist.extend(vec![i, i, -1]);
+            hist
+        });
+        let mut merged = merge_all(shards).unwrap();
+        merged.merge(Histogram::default());
+        assert_eq!(merged.counts(), &[2, 2, 2, 2]);
+        assert_eq!(merged.underflow(), 4);
+
+        let mut empty = Histogram::default();
+        empty.merge(merged.clone());
+        assert_eq!(empty.counts(), merged.counts());
+        assert_eq!(empty.edges(), merged.edges());
+    }
+
+    #[test]
+    fn rules() {
+        let mut data: Unsorted<u64> = (0..1000).map(|x| x % 100).collect();
+        let bins = |data: &mut Unsorted<u64>, rule| {
+            Histogram::from_unsorted(data, rule).unwrap().counts().len()
+        };
+        assert_eq!(bins(&mut data, BinRule::Sturges), 11);
+        assert_eq!(bins(&mut data, BinRule::Sqrt), 32);
+        // stddev = 28.866 and IQR = 49.5 over a range of 99.
+        assert_eq!(bins(&mut data, BinRule::Scott), 10);
+        assert_eq!(bins(&mut data, BinRule::FreedmanDiaconis), 10);
+        // Uniform data has no skew, so Doane agrees with Sturges.
+        assert_eq!(bins(&mut data, BinRule::Doane), 11);
+
+        let hist = Histogram::from_unsorted(&mut data, BinRule::Sturges)
+                             .unwrap();
+        assert_eq!(hist.len(), 1000);
+        assert_eq!(hist.underflow() + hist.overflow(), 0);
+        assert_eq!(hist.edges()[0], 0.0);
+        assert_eq!(hist.edges()[11], 99.0);
+    }
+
+    #[test]
+    fn rules_skewed() {
+        let mut data: Unsorted<f64> =
+            (1..1001).map(|x| 1.0 / x as f64).collect();
+        let doane = Histogram::from_unsorted(&mut data, BinRule::Doane)
+                              .unwrap();
+        assert!(doane.counts().len() > 11);
+    }
+
+    #[test]
+    fn rules_degenerate() {
+        let mut same: Unsorted<i32> = vec![5; 10].into_iter().collect();
+        let hist = Histogram::from_unsorted(&mut same,
+                                            BinRule::FreedmanDiaconis)
+                             .unwrap();
+        assert_eq!(hist.edges(), &[4.5, 5.5]);
+        assert_eq!(hist.counts(), &[10]);
+        assert!(Histogram::from_unsorted(&mut Unsorted::<i32>::new(),
+                                         BinRule::Scott).is_none());
+
+        // Most samples are equal, so the IQR is zero.
+        let mut spiked: Unsorted<u32> =
+            vec![1; 90].into_iter().chain(0..10).collect();
+        let hist = Histogram::from_unsorted(&mut spiked,
+                                            BinRule::FreedmanDiaconis)
+                             .unwrap();
+        assert_eq!(hist.counts().len(), 8);
+    }
+
+    #[test]
+    fn rules_outliers() {
+        // A tiny IQR next to a huge range asks for far too many bins.
+        let mut data: Unsorted<f64> =
+            (0..1000).map(|i| i as f64 * 1e-12).collect();
+        data.add(1e9);
+        for &rule in &[BinRule::FreedmanDiaconis, BinRule::Scott] {
+            let hist = Histogram::from_unsorted(&mut data, rule).unwrap();
+            assert!(hist.counts().len() <= 1001);
+            assert_eq!(hist.len(), 1001);
+        }
+
+        // Bins narrower than the spacing of floats near the data.
+        let mut data: Unsorted<f64> =
+            (0..1000).map(|i| 1e15 + (i % 2) as f64 * 0.5).collect();
+        let hist = Histogram::from_unsorted(&mut data, BinRule::Sqrt)
+                             .unwrap();
+        assert_eq!(hist.counts().len(), 1);
+        assert_eq!(hist.len(), 1000);
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::default::Default;
+use std::hash::{Hash, Hasher};
+use std::iter::{FromIterator, IntoIterator};
+use std::marker::PhantomData;
+use std::mem;
+
+use Commute;
+
+/// The precision of the sparse representation.
+const SPARSE_PRECISION: u8 = 25;
+
+/// A commutative sketch for approximately counting distinct values.
+///
+/// This is HyperLogLog with the improvements of HLL++ (Heule, Nunkesser
+/// and Hall, 2013): small sets are kept in a sparse representation with a
+/// much higher precision, and it only switches to `2^precision` one byte
+/// registers once the sparse map has allocated more memory than they need.
+/// Instead of HLL++'s empirical bias tables, cardinalities are estimated
+/// with Ertl's improved estimator (2017), which has no bias to correct for
+/// at any cardinality.
+///
+/// The standard error of `cardinality()` is about `1.04 / sqrt(2^p)` for a
+/// precision `p`. Values are hashed with the standard library's
+/// `DefaultHasher`, so sketches should only be merged with sketches built
+/// by the same version of Rust.
+///
+/// Sketches with different precisions can be merged: the result has the
+/// lower of the two precisions. Merging with an empty sketch leaves the
+/// other one unchanged, whatever its precision.
+#[derive(Clone, Debug)]
+pub struct HyperLogLog<T> {
+    precision: u8,
+    repr: Repr,
+    _marker: PhantomData<fn(T)>,
+}
+
+#[derive(Clone, Debug)]
+enum Repr {
+    // Maps register indices at `SPARSE_PRECISION` to register values.
+    Sparse(HashMap<u32, u8>),
+    Dense(Vec<u8>),
+}
+
+impl<T: Hash> HyperLogLog<T> {
+    /// Create an empty sketch with the default precision of `14`.
+    pub fn new() -> HyperLogLog<T> {
+        Default::default()
+    }
+
+    /// Create an empty sketch with `2^precision` registers.
+    ///
+    /// `precision` must be in `[4, 18]`.
+    pub fn with_precision(precision: u8) -> HyperLogLog<T> {
+        assert!((4..=18).contains(&precision),
+                "precision {} is not in [4, 18]", precision);
+        HyperLogLog {
+            precision,
+            repr: Repr::Sparse(HashMap::new()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Add a value to the sketch.
+    pub fn add(&mut self, v: T) {
+        let mut hasher = DefaultHasher::new();
+        v.hash(&mut hasher);
+        let hash = hasher.finish();
+        match self.repr {
+            Repr::Sparse(ref mut sparse) => {
+                let (i, rho) = split(hash, SPARSE_PRECISION);
+                let r = sparse.entry(i).or_insert(0);
+                *r = (*r).max(rho);
+            }
+            Repr::Dense(ref mut registers) => {
+                let (i, rho) = split(hash, self.precision);
+                let r = &mut registers[i as usize];
+                *r = (*r).max(rho);
+            }
+        }
+        self.maybe_densify();
+    }
+
+    /// Returns the precision of this sketch.
+    pub fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    /// Returns the standard error of the estimate relative to the true
+    /// cardinality, once the sketch has switched to registers.
+    pub fn standard_error(&self) -> f64 {
+        1.04 / ((1u64 << self.precision) as f64).sqrt()
+    }
+
+    /// Returns true if no values have been added.
+    pub fn is_empty(&self) -> bool {
+        match self.repr {
+            Repr::Sparse(ref sparse) => sparse.is_empty(),
+            Repr::Dense(ref registers) => registers.iter().all(|&r| r == 0),
+        }
+    }
+
+    /// Returns the estimated number of distinct values added.
+    pub fn cardinality(&self) -> u64 {
+        let (p, counts) = match self.repr {
+            Repr::Sparse(ref sparse) => {
+                let p = SPARSE_PRECISION;
+                (p, histogram(p, sparse.values().cloned()))
+            }
+            Repr::Dense(ref registers) => {
+                let p = self.precision;
+                (p, histogram(p, registers.iter().cloned()))
+            }
+        };
+        estimate(p, &counts).round() as u64
+    }
+
+    /// Switches to registers once the sparse representation stops saving
+    /// memory.
+    fn maybe_densify(&mut self) {
+        // Each slot of the map holds an entry and a control byte.
+        let slot = mem::size_of::<(u32, u8)>() + 1;
+        let registers = match self.repr {
+            Repr::Sparse(ref sparse)
+                    if sparse.capacity() * slot > 1 << self.precision => {
+                let mut registers = vec![0; 1 << self.precision];
+                for (&i, &rho) in sparse {
+                    let (i, rho) = fold(i, rho, SPARSE_PRECISION,
+                                        self.precision);
+                    let r = &mut registers[i as usize];
+                    *r = (*r).max(rho);
+                }
+                registers
+            }
+            _ => return,
+        };
+        self.repr = Repr::Dense(registers);
+    }
+
+    /// Lowers the precision of this sketch to `precision`.
+    fn reduce(&mut self, precision: u8) {
+        if precision >= self.precision {
+            return;
+        }
+        if let Repr::Dense(ref mut registers) = self.repr {
+            let mut reduced = vec![0; 1 << precision];
+            for (i, &rho) in registers.iter().enumerate() {
+                if rho > 0 {
+                    let (i, rho) = fold(i as u32, rho, self.precision,
+                                        precision);
+                    let r = &mut reduced[i as usize];
+                    *r = (*r).max(rho);
+                }
+            }
+            *registers = reduced;
+        }
+        self.precision = precision;
+    }
+}
+
+/// Splits a hash into a register index of `p` bits and the position of
+/// the first set bit in the rest.
+fn split(hash: u64, p: u8) -> (u32, u8) {
+    let i = (hash >> (64 - p)) as u32;
+    let rho = ((hash << p).leading_zeros() + 1).min(64 - p as u32 + 1);
+    (i, rho as u8)
+}
+
+/// Maps a register at precision `from` to the register at the lower
+/// precision `to` that the same hash would have been counted in.
+fn fold(i: u32, rho: u8, from: u8, to: u8) -> (u32, u8) {
+    let d = (from - to) as u32;
+    // The bits dropped from the index now come first in the rest of the
+    // hash.
+    let dropped = i & ((1 << d) - 1);
+    let rho = if dropped == 0 {
+        d as u8 + rho
+    } else {
+        (dropped.leading_zeros() - (32 - d) + 1) as u8
+    };
+    (i >> d, rho)
+}
+
+/// Returns how many of the `2^p` registers have each possible value.
+///
+/// Registers missing from `registers` are zero.
+fn histogram<I>(p: u8, registers: I) -> Vec<f64>
+        where I: Iterator<Item=u8> {
+    let mut counts = vec![0.0; 64 - p as usize + 2];
+    let mut seen = 0;
+    for r in registers {
+        counts[r as usize] += 1.0;
+        seen += 1;
+    }
+    counts[0] += ((1u64 << p) - seen) as f64;
+    counts
+}
+
+/// Ertl's improved raw estimator from a histogram of register values.
+fn estimate(p: u8, counts: &[f64]) -> f64 {
+    let m = (1u64 << p) as f64;
+    let q = 64 - p as usize;
+    let mut z = m * tau(1.0 - counts[q + 1] / m);
+    for k in (1..q + 1).rev() {
+        z = 0.5 * (z + counts[k]);
+    }
+    z += m * sigma(counts[0] / m);
+    m * m / (2.0 * 2f64.ln() * z)
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let prev = z;
+        z += x * y;
+        y += y;
+        if z == prev {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let prev = z;
+        y *= 0.5;
+        z -= (1.0 - x) * (1.0 - x) * y;
+        if z == prev {
+            return z / 3.0;
+        }
+    }
+}
+
+impl<T: Hash> Commute for HyperLogLog<T> {
+    fn merge(&mut self, mut v: HyperLogLog<T>) {
+        if v.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = v;
+            return;
+        }
+        let precision = self.precision.min(v.precision);
+        self.reduce(precision);
+        v.reduce(precision);
+        let repr = ::std::mem::replace(&mut self.repr, Repr::Dense(vec![]));
+        self.repr = match (repr, v.repr) {
+            (Repr::Sparse(mut a), Repr::Sparse(b)) => {
+                for (i, rho) in b {
+                    let r = a.entry(i).or_insert(0);
+                    *r = (*r).max(rho);
+                }
+                Repr::Sparse(a)
+            }
+            (Repr::Dense(mut a), Repr::Dense(b)) => {
+                for (r, rho) in a.iter_mut().zip(b) {
+                    *r = (*r).max(rho);
+                }
+                Repr::Dense(a)
+            }
+            (Repr::Dense(mut a), Repr::Sparse(b))
+                    | (Repr::Sparse(b), Repr::Dense(mut a)) => {
+                for (i, rho) in b {
+                    let (i, rho) = fold(i, rho, SPARSE_PRECISION, precision);
+                    let r = &mut a[i as usize];
+                    *r = (*r).max(rho);
+                }
+                Repr::Dense(a)
+            }
+        };
+        self.maybe_densify();
+    }
+}
+
+impl<T: Hash> Default for HyperLogLog<T> {
+    fn default() -> HyperLogLog<T> {
+        HyperLogLog::with_precision(14)
+    }
+}
+
+impl<T: Hash> FromIterator<T> for HyperLogLog<T> {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> HyperLogLog<T> {
+        let mut v = HyperLogLog::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: Hash> Extend<T> for HyperLogLog<T> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use super::{HyperLogLog, Repr};
+
+    fn within(hll: &HyperLogLog<u64>, exact: u64, error: f64) -> bool {
+        let got = hll.cardinality() as f64;
+        (got - exact as f64).abs() <= error * exact as f64
+    }
+
+    #[test]
+    fn small() {
+        let hll: HyperLogLog<&str> =
+            vec!["a", "b", "a", "c", "b"].into_iter().collect();
+        assert_eq!(hll.cardinality(), 3);
+        assert!(HyperLogLog::<u64>::new().is_empty());
+        assert_eq!(HyperLogLog::<u64>::new().cardinality(), 0);
+
+        let hll: HyperLogLog<u64> = (0..1000).collect();
+        assert!(within(&hll, 1000, 0.01));
+    }
+
+    #[test]
+    fn large() {
+        for &n in &[10_000, 100_000, 1_000_000] {
+            let mut hll = HyperLogLog::with_precision(14);
+            for i in 0..n {
+                hll.add(i);
+                hll.add(i);
+            }
+            match hll.repr {
+                Repr::Dense(ref registers) => {
+                    assert_eq!(registers.len(), 1 << 14);
+                }
+                Repr::Sparse(_) => panic!("expected registers"),
+            }
+            assert!(within(&hll, n, 3.0 * hll.standard_error()),
+                    "n = {}: got {}", n, hll.cardinality());
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let n = 200_000u64;
+        let parts = (0..10).map(|k| {
+            let mut hll = HyperLogLog::with_precision(12);
+            hll.extend((0..n).filter(|i| i % 10 == k));
+            hll
+        });
+        let mut merged = merge_all(parts).unwrap();
+        merged.merge(HyperLogLog::new());
+        let whole: HyperLogLog<u64> = {
+            let mut hll = HyperLogLog::with_precision(12);
+            hll.extend(0..n);
+            hll
+        };
+        assert_eq!(merged.cardinality(), whole.cardinality());
+        assert!(within(&merged, n, 3.0 * merged.standard_error()));
+    }
+
+    #[test]
+    fn merge_precisions() {
+        let mut a = HyperLogLog::with_precision(16);
+        a.extend(0..100_000u64);
+        let mut b = HyperLogLog::with_precision(10);
+        b.extend(50_000..150_000u64);
+        let mut sparse = HyperLogLog::with_precision(12);
+        sparse.extend(149_900..150_100u64);
+
+        a.merge(b);
+        a.merge(sparse);
+        assert_eq!(a.precision(), 10);
+        assert!(within(&a, 150_100, 3.0 * a.standard_error()));
+
+        let mut direct = HyperLogLog::with_precision(10);
+        direct.extend(0..150_100u64);
+        assert_eq!(a.cardinality(), direct.cardinality());
+
+        let mut empty = HyperLogLog::with_precision(10);
+        let mut b = HyperLogLog::with_precision(16);
+        b.extend(0..100_000u64);
+        empty.merge(b.clone());
+        assert_eq!(empty.precision(), 16);
+        assert_eq!(empty.cardinality(), b.cardinality());
+    }
+
+    #[test]
+    fn merge_default() {
+        let mut a = HyperLogLog::with_precision(16);
+        a.extend(0..100_000u64);
+        let before = a.cardinality();
+        a.merge(HyperLogLog::new());
+        assert_eq!(a.precision(), 16);
+        assert_eq!(a.cardinality(), before);
+    }
+}
@@ -0,0 +1,328 @@
+use std::default::Default;
+use std::iter::{FromIterator, IntoIterator};
+
+use Commute;
+use Partial;
+use rng::Rng;
+
+/// A commutative sketch for approximate ranks and quantiles in bounded
+/// memory.
+///
+/// This is the KLL sketch of Karnin, Lang and Liberty (2016). Unlike
+/// `TDigest`, it works for any type with an ordering and its rank error
+/// has a bound that doesn't depend on the input: with probability at least
+/// 99%, every rank (and so every quantile) is within
+/// `normalized_rank_error()` of the exact one. The sketch stores
+/// `O(k)` items, where larger values of the parameter `k` are more
+/// accurate.
+///
+/// Like `Unsorted`, this works with types that do not define a total
+/// ordering like `f64`.
+#[derive(Clone)]
+pub struct Kll<T> {
+    k: usize,
+    count: u64,
+    min: Option<Partial<T>>,
+    max: Option<Partial<T>>,
+    // Items in `levels[h]` each stand for `2^h` samples.
+    levels: Vec<Vec<Partial<T>>>,
+    // The number of items in all levels, and how many they can hold before
+    // one of them must be compacted.
+    retained: usize,
+    max_retained: usize,
+    rng: Rng,
+}
+
+impl<T: PartialOrd + Clone> Kll<T> {
+    /// Create an empty sketch with the default `k` of `200`.
+    pub fn new() -> Kll<T> {
+        Default::default()
+    }
+
+    /// Create an empty sketch with the given accuracy parameter `k`.
+    ///
+    /// The randomness used for compaction is seeded from the environment.
+    pub fn with_k(k: usize) -> Kll<T> {
+        Kll::with_seed(k, Rng::from_entropy().next_u64())
+    }
+
+    /// Create an empty sketch with the given accuracy parameter `k` whose
+    /// compactions are seeded by `seed`.
+    ///
+    /// The same seed and the same data always produce the same sketch.
+    pub fn with_seed(k: usize, seed: u64) -> Kll<T> {
+        assert!(k >= 2, "k {} must be at least 2", k);
+        Kll {
+            k,
+            count: 0,
+            min: None,
+            max: None,
+            levels: vec![vec![]],
+            retained: 0,
+            max_retained: k,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Add a sample to the sketch.
+    pub fn add(&mut self, sample: T) {
+        let sample = Partial(sample);
+        self.count += 1;
+        if self.min.as_ref().map(|v| &sample < v).unwrap_or(true) {
+            self.min = Some(sample.clone());
+        }
+        if self.max.as_ref().map(|v| &sample > v).unwrap_or(true) {
+            self.max = Some(sample.clone());
+        }
+        self.levels[0].push(sample);
+        self.retained += 1;
+        if self.retained >= self.max_retained {
+            self.compress();
+        }
+    }
+
+    /// Returns the number of samples added to the sketch.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Returns true if no samples have been added to the sketch.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the accuracy parameter `k`.
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// Returns the approximate fraction of samples that are at most `v`.
+    ///
+    /// `None` is returned if and only if there is no data.
+    pub fn rank(&self, v: &T) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let mut weight = 0u64;
+        for (h, level) in self.levels.iter().enumerate() {
+            let below = level.iter().filter(|x| &x.0 <= v).count() as u64;
+            weight += below << h;
+        }
+        Some(weight as f64 / self.count as f64)
+    }
+
+    /// Returns the approximate quantile `p` of the data, where `p` is in
+    /// `[0, 1]`.
+    ///
+    /// This is the smallest retained sample whose approximate rank is at
+    /// least `p`. The quantiles `0` and `1` are always the exact minimum
+    /// and maximum. `None` is returned if and only if there is no data.
+    pub fn quantile(&self, p: f64) -> Option<T> {
+        assert!((0.0..=1.0).contains(&p), "quantile {} is not in [0, 1]", p);
+        if self.count == 0 {
+            return None;
+        }
+        if p == 0.0 {
+            return self.min.as_ref().map(|v| v.0.clone());
+        }
+        if p == 1.0 {
+            return self.max.as_ref().map(|v| v.0.clone());
+        }
+        let mut weighted: Vec<(&Partial<T>, u64)> = vec![];
+        for (h, level) in self.levels.iter().enumerate() {
+            weighted.extend(level.iter().map(|v| (v, 1u64 << h)));
+        }
+        weighted.sort_by(|a, b| a.0.cmp(b.0));
+
+        let target = p * self.count as f64;
+        let mut cumulative = 0u64;
+        for &(v, w) in &weighted {
+            cumulative += w;
+            if cumulative as f64 >= target {
+                return Some(v.0.clone());
+            }
+        }
+        self.max.as_ref().map(|v| v.0.clone())
+    }
+
+    /// Returns the bound on the normalized rank error of this sketch.
+    ///
+    /// With probability at least 99%, `rank` and `quantile` are off by at
+    /// most this fraction of the number of samples. This only depends on
+    /// `k`; the constants are the empirical fit used by Apache DataSketches.
+    pub fn normalized_rank_error(&self) -> f64 {
+        2.296 / (self.k as f64).powf(0.9723)
+    }
+
+    /// Returns the maximum number of items level `h` can hold before it
+    /// must be compacted.
+    fn capacity(&self, h: usize) -> usize {
+        // Capacities shrink geometrically by `2/3` going down from the top
+        // level, which is what keeps the total size `O(k)`.
+        let depth = (self.levels.len() - 1 - h) as i32;
+        let cap = (self.k as f64 * (2.0f64 / 3.0).powi(depth)).ceil();
+        (cap as usize).max(2)
+    }
+
+    /// Recomputes `max_retained` after the levels or `k` changed.
+    fn update_max_retained(&mut self) {
+        self.max_retained =
+            (0..self.levels.len()).map(|h| self.capacity(h)).sum();
+    }
+
+    /// Compacts the lowest full level until the sketch fits its capacity.
+    fn compress(&mut self) {
+        while self.retained >= self.max_retained {
+            let h = (0..self.levels.len())
+                    .find(|&h| self.levels[h].len() >= self.capacity(h))
+                    .unwrap();
+            if h + 1 == self.levels.len() {
+                self.levels.push(vec![]);
+                self.update_max_retained();
+            }
+            let mut level = ::std::mem::take(&mut self.levels[h]);
+            level.sort();
+            // An odd item out stays behind at this level.
+            if level.len() % 2 == 1 {
+                let last = level.pop().unwrap();
+                self.levels[h].push(last);
+            }
+            // Keeping every other item, starting at a random one, doubles
+            // the weight of what's kept while leaving ranks unbiased.
+            let offset = if self.rng.next_bool() { 1 } else { 0 };
+            self.retained -= level.len() / 2;
+            let promoted = level.into_iter().skip(offset).step_by(2);
+            self.levels[h + 1].extend(promoted);
+        }
+    }
+}
+
+impl<T: PartialOrd + Clone> Commute for Kll<T> {
+    fn merge(&mut self, v: Kll<T>) {
+        // An empty sketch must not lower the `k` of the other one.
+        if v.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = v;
+            return;
+        }
+        self.k = self.k.min(v.k);
+        self.count += v.count;
+        if let Some(min) = v.min {
+            if self.min.as_ref().map(|v| &min < v).unwrap_or(true) {
+                self.min = Some(min);
+            }
+        }
+        if v.max > self.max {
+            self.max = v.max;
+        }
+        for (h, level) in v.levels.into_iter().enumerate() {
+            if h == self.levels.len() {
+                self.levels.push(vec![]);
+            }
+            self.levels[h].extend(level);
+        }
+        self.retained += v.retained;
+        self.update_max_retained();
+        self.compress();
+    }
+}
+
+impl<T: PartialOrd + Clone> Default for Kll<T> {
+    fn default() -> Kll<T> {
+        Kll::with_k(200)
+    }
+}
+
+impl<T: PartialOrd + Clone> FromIterator<T> for Kll<T> {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> Kll<T> {
+        let mut v = Kll::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: PartialOrd + Clone> Extend<T> for Kll<T> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use testutil::shuffled;
+    use super::Kll;
+
+    fn check_error(sketch: &Kll<u64>, n: u64) {
+        let eps = sketch.normalized_rank_error();
+        for i in 1..100 {
+            let p = i as f64 / 100.0;
+            let q = sketch.quantile(p).unwrap();
+            let exact = (q + 1) as f64 / n as f64;
+            assert!((exact - p).abs() <= eps,
+                    "p = {}: got {} with rank {}", p, q, exact);
+            let v = (p * n as f64) as u64;
+            let rank = sketch.rank(&v).unwrap();
+            assert!((rank - (v + 1) as f64 / n as f64).abs() <= eps);
+        }
+    }
+
+    #[test]
+    fn bounded() {
+        let n = 100_000;
+        let mut sketch = Kll::with_seed(200, 42);
+        sketch.extend(shuffled(n));
+        assert_eq!(sketch.len(), n as usize);
+        let retained: usize = sketch.levels.iter().map(|l| l.len()).sum();
+        assert_eq!(sketch.retained, retained);
+        assert!(retained < 3 * 200 + 2 * sketch.levels.len());
+        assert_eq!(sketch.quantile(0.0), Some(0));
+        assert_eq!(sketch.quantile(1.0), Some(n - 1));
+        check_error(&sketch, n);
+    }
+
+    #[test]
+    fn merge() {
+        let n = 100_000;
+        let xs = shuffled(n);
+        let sketches = xs.chunks(9_973).enumerate().map(|(i, chunk)| {
+            let mut sketch = Kll::with_seed(200, i as u64);
+            sketch.extend(chunk.iter().cloned());
+            sketch
+        });
+        let mut merged = merge_all(sketches).unwrap();
+        merged.merge(Kll::new());
+        assert_eq!(merged.len(), n as usize);
+        assert_eq!(merged.quantile(0.0), Some(0));
+        assert_eq!(merged.quantile(1.0), Some(n - 1));
+        check_error(&merged, n);
+    }
+
+    #[test]
+    fn merge_empty() {
+        let mut sketch = Kll::with_seed(400, 7);
+        sketch.extend(0..1000u64);
+        sketch.merge(Kll::new());
+        assert_eq!(sketch.k(), 400);
+        let mut empty = Kll::new();
+        empty.merge(sketch);
+        assert_eq!(empty.k(), 400);
+        assert_eq!(empty.len(), 1000);
+    }
+
+    #[test]
+    fn strings() {
+        let words = ["pear", "apple", "fig", "kiwi", "banana", "cherry"];
+        let sketch: Kll<String> =
+            words.iter().map(|w| w.to_string()).collect();
+        assert_eq!(sketch.quantile(0.0), Some("apple".to_string()));
+        assert_eq!(sketch.quantile(0.5), Some("cherry".to_string()));
+        assert_eq!(sketch.quantile(1.0), Some("pear".to_string()));
+        assert_eq!(sketch.rank(&"cherry".to_string()), Some(0.5));
+        assert_eq!(Kll::<String>::new().quantile(0.5), None);
+    }
+}
@@ -4,16 +4,39 @@ use std::cmp::Ordering;
 use std::hash;
 use num::ToPrimitive;
 
+pub use chisquare::{ChiSquare, Contingency};
+pub use countmin::{CountMinMergeError, CountMinSketch};
+pub use covariance::{
+    OnlineCovariance, OnlineCovarianceMatrix, correlation, covariance,
+};
+pub use ddsketch::{Collapse, DDSketch};
+pub use ewma::Ewma;
 pub use frequency::Frequencies;
+pub use gk::GreenwaldKhanna;
+pub use hdr::{HdrHistogram, Recorded};
+pub use histogram::{BinRule, Histogram};
+pub use hyperloglog::HyperLogLog;
+pub use kll::Kll;
 pub use minmax::MinMax;
-pub use online::{OnlineStats, stddev, variance, mean};
-pub use unsorted::{Unsorted, median, mode};
+pub use online::{
+    OnlineStats, stddev, stddev_ddof, variance, variance_ddof, mean,
+};
+pub use psquare::{ApproxPSquare, PSquare};
+pub use reservoir::{Reservoir, ReservoirAlgorithm, WeightedReservoir};
+pub use spacesaving::SpaceSaving;
+pub use tdigest::TDigest;
+pub use unsorted::{
+    Interpolation, Unsorted, WeightedUnsorted, antimodes, median, mode,
+    modes, quantile,
+};
+pub use window::{Evict, MovingMedian, MovingMinMax, TimeWindow, Window};
 
 /// Partial wraps a type that satisfies `PartialOrd` and implements `Ord`.
 ///
 /// This allows types like `f64` to be used in data structures that require
 /// `Ord`. When an ordering is not defined, an arbitrary order is returned.
-#[derive(Clone, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[allow(clippy::derive_ord_xor_partial_ord)]
 struct Partial<T>(pub T);
 
 impl<T: PartialEq> Eq for Partial<T> {}
@@ -45,6 +68,78 @@ impl<T: hash::Hash> hash::Hash for Partial<T> {
     fn hash<H: hash::Hasher>(&self, state: &mut H) { self.0.hash(state); }
 }
 
+/// The most (or least) frequent values of a data set.
+///
+/// Unlike an `Option`, this tells data without any values apart from data
+/// where several values are tied.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ModeResult<T> {
+    /// There is no data.
+    Empty,
+    /// A single value, with the number of times it occurs.
+    Unique(T, u64),
+    /// Several values that each occur the given number of times.
+    Multiple(Vec<T>, u64),
+}
+
+impl<T> ModeResult<T> {
+    /// Builds the result for `values` that are tied at `count`.
+    fn from_ties(mut values: Vec<T>, count: u64) -> ModeResult<T> {
+        match values.len() {
+            0 => ModeResult::Empty,
+            1 => ModeResult::Unique(values.pop().unwrap(), count),
+            _ => ModeResult::Multiple(values, count),
+        }
+    }
+
+    /// Returns the value if there is exactly one.
+    pub fn unique(self) -> Option<T> {
+        match self {
+            ModeResult::Unique(v, _) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// Applies `f` to every value, keeping the count.
+    pub fn map<U, F: FnMut(T) -> U>(self, mut f: F) -> ModeResult<U> {
+        match self {
+            ModeResult::Empty => ModeResult::Empty,
+            ModeResult::Unique(v, n) => ModeResult::Unique(f(v), n),
+            ModeResult::Multiple(vs, n) => {
+                ModeResult::Multiple(vs.into_iter().map(f).collect(), n)
+            }
+        }
+    }
+
+    /// Returns the number of times each value occurs, or `0` if there is
+    /// no data.
+    pub fn count(&self) -> u64 {
+        match *self {
+            ModeResult::Empty => 0,
+            ModeResult::Unique(_, n) | ModeResult::Multiple(_, n) => n,
+        }
+    }
+}
+
+/// Returns the values whose count is the largest (or the smallest) of the
+/// given `(value, count)` pairs.
+fn extreme_counts<T, I>(counts: I, most: bool) -> ModeResult<T>
+        where I: Iterator<Item=(T, u64)> {
+    let mut best = 0;
+    let mut values = vec![];
+    for (v, n) in counts {
+        let better = if most { n > best } else { n < best };
+        if values.is_empty() || better {
+            best = n;
+            values.clear();
+            values.push(v);
+        } else if n == best {
+            values.push(v);
+        }
+    }
+    ModeResult::from_ties(values, best)
+}
+
 /// Defines an interface for types that have an identity and can be commuted.
 ///
 /// The value returned by `Default::default` must be its identity with respect
@@ -75,7 +170,9 @@ impl<T: Commute> Commute for Option<T> {
     fn merge(&mut self, other: Option<T>) {
         match *self {
             None => { *self = other; }
-            Some(ref mut v1) => { other.map(|v2| v1.merge(v2)); }
+            Some(ref mut v1) => {
+                if let Some(v2) = other { v1.merge(v2); }
+            }
         }
     }
 }
@@ -106,16 +203,34 @@ impl<T: Commute, E> Commute for Result<T, E> {
 impl<T: Commute> Commute for Vec<T> {
     fn merge(&mut self, other: Vec<T>) {
         assert_eq!(self.len(), other.len());
-        for (v1, v2) in self.iter_mut().zip(other.into_iter()) {
+        for (v1, v2) in self.iter_mut().zip(other) {
             v1.merge(v2);
         }
     }
 }
 
+mod chisquare;
+mod countmin;
+mod covariance;
+mod ddsketch;
+mod ewma;
 mod frequency;
+mod gk;
+mod hdr;
+mod histogram;
+mod hyperloglog;
+mod kll;
 mod minmax;
 mod online;
+mod psquare;
+mod reservoir;
+mod rng;
+mod spacesaving;
+mod tdigest;
+#[cfg(test)]
+mod testutil;
 mod unsorted;
+mod window;
 
 #[cfg(test)]
 mod test {
@@ -31,6 +31,22 @@ impl<T: PartialOrd + Clone> MinMax<T> {
         }
     }
 
+    /// Add a weighted sample to the data.
+    ///
+    /// The weight of a sample doesn't change the minimum or maximum, but
+    /// samples with a weight of `0` are ignored entirely.
+    ///
+    /// # Panics
+    ///
+    /// If `weight` is negative, infinite or NaN.
+    pub fn add_weighted(&mut self, sample: T, weight: f64) {
+        assert!(weight.is_finite() && weight >= 0.0,
+                "sample weight {} is negative or not finite", weight);
+        if weight > 0.0 {
+            self.add(sample);
+        }
+    }
+
     /// Returns the minimum of the data set.
     ///
     /// `None` is returned if and only if the number of samples is `0`.
@@ -49,12 +65,21 @@ impl<T: PartialOrd + Clone> MinMax<T> {
     pub fn len(&self) -> usize {
         self.len as usize
     }
+
+    /// Returns true if there are no data points.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 impl<T: PartialOrd> Commute for MinMax<T> {
     fn merge(&mut self, v: MinMax<T>) {
         self.len += v.len;
-        if v.min < self.min { self.min = v.min; }
+        // `None` compares less than everything, so an empty `v` must not
+        // replace our minimum.
+        if v.min.is_some() && (self.min.is_none() || v.min < self.min) {
+            self.min = v.min;
+        }
         if v.max > self.max { self.max = v.max; }
     }
 }
@@ -72,10 +97,10 @@ impl<T: PartialOrd> Default for MinMax<T> {
 impl<T: fmt::Debug> fmt::Debug for MinMax<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match (&self.min, &self.max) {
-            (&Some(ref min), &Some(ref max)) => {
+            (Some(min), Some(max)) => {
                 write!(f, "[{:?}, {:?}]", min, max)
             }
-            (&None, &None) => write!(f, "N/A"),
+            (None, None) => write!(f, "N/A"),
             _ => unreachable!(),
         }
     }
@@ -99,6 +124,7 @@ impl<T: PartialOrd + Clone> Extend<T> for MinMax<T> {
 
 #[cfg(test)]
 mod test {
+    use Commute;
     use super::MinMax;
 
     #[test]
@@ -108,4 +134,28 @@ mod test {
         assert_eq!(minmax.min(), Some(&1usize));
         assert_eq!(minmax.max(), Some(&10usize));
     }
+
+    #[test]
+    fn minmax_weighted() {
+        let mut minmax = MinMax::new();
+        minmax.add_weighted(5usize, 0.5);
+        minmax.add_weighted(1usize, 0.0);
+        minmax.add_weighted(7usize, 2.0);
+        assert_eq!(minmax.min(), Some(&5usize));
+        assert_eq!(minmax.max(), Some(&7usize));
+        assert_eq!(minmax.len(), 2);
+    }
+
+    #[test]
+    fn minmax_merge_empty() {
+        let mut minmax: MinMax<usize> = vec![3usize, 8].into_iter().collect();
+        minmax.merge(MinMax::new());
+        assert_eq!(minmax.min(), Some(&3usize));
+        assert_eq!(minmax.max(), Some(&8usize));
+
+        let mut empty = MinMax::new();
+        empty.merge(minmax);
+        assert_eq!(empty.min(), Some(&3usize));
+        assert_eq!(empty.len(), 2);
+    }
 }
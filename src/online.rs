@@ -2,40 +2,72 @@ use std::default::Default;
 use std::fmt;
 use std::iter::{FromIterator, IntoIterator};
 
-use num::{Float, ToPrimitive};
+use num::ToPrimitive;
 
 use Commute;
 
 /// Compute the standard deviation of a stream in constant space.
+///
+/// This is the population standard deviation. Use `stddev_ddof` with
+/// `ddof = 1` for the sample standard deviation.
 pub fn stddev<I>(it: I) -> f64
         where I: Iterator, <I as Iterator>::Item: ToPrimitive {
     it.collect::<OnlineStats>().stddev()
 }
 
+/// Compute the standard deviation of a stream in constant space with
+/// `ddof` delta degrees of freedom.
+pub fn stddev_ddof<I>(it: I, ddof: usize) -> f64
+        where I: Iterator, <I as Iterator>::Item: ToPrimitive {
+    it.collect::<OnlineStats>().stddev_ddof(ddof)
+}
+
 /// Compute the variance of a stream in constant space.
+///
+/// This is the population variance. Use `variance_ddof` with `ddof = 1`
+/// for the sample variance.
 pub fn variance<I>(it: I) -> f64
         where I: Iterator, <I as Iterator>::Item: ToPrimitive {
     it.collect::<OnlineStats>().variance()
 }
 
+/// Compute the variance of a stream in constant space with `ddof` delta
+/// degrees of freedom.
+///
+/// The sum of squared differences from the mean is divided by
+/// `len - ddof`. So `ddof = 0` gives the population variance and
+/// `ddof = 1` gives the (Bessel-corrected) sample variance.
+pub fn variance_ddof<I>(it: I, ddof: usize) -> f64
+        where I: Iterator, <I as Iterator>::Item: ToPrimitive {
+    it.collect::<OnlineStats>().variance_ddof(ddof)
+}
+
 /// Compute the mean of a stream in constant space.
 pub fn mean<I>(it: I) -> f64
         where I: Iterator, <I as Iterator>::Item: ToPrimitive {
     it.collect::<OnlineStats>().mean()
 }
 
-/// Online state for computing mean, variance and standard deviation.
+/// Online state for computing mean, variance, standard deviation, skewness
+/// and kurtosis.
 #[derive(Clone, Copy)]
 pub struct OnlineStats {
     size: u64,
+    // Sums of the sample weights and of their squares.
+    weight: f64,
+    weight_sq: f64,
     mean: f64,
-    variance: f64,
+    // Sums of the 2nd, 3rd and 4th powers of the differences from the mean.
+    m2: f64,
+    m3: f64,
+    m4: f64,
 }
 
 impl OnlineStats {
     /// Create initial state.
     ///
-    /// Population size, variance and mean are set to `0`.
+    /// Population size, variance, mean and all higher moments are set
+    /// to `0`.
     pub fn new() -> OnlineStats {
         Default::default()
     }
@@ -51,27 +83,212 @@ impl OnlineStats {
     }
 
     /// Return the current standard deviation.
+    ///
+    /// This is the population standard deviation.
     pub fn stddev(&self) -> f64 {
-        self.variance.sqrt()
+        self.variance().sqrt()
     }
 
     /// Return the current variance.
+    ///
+    /// This is the population variance, which is `0` if there is no data.
     pub fn variance(&self) -> f64 {
-        self.variance
+        if self.weight == 0.0 { 0.0 } else { self.m2 / self.weight }
+    }
+
+    /// Return the current population standard deviation.
+    pub fn population_stddev(&self) -> f64 {
+        self.stddev()
+    }
+
+    /// Return the current population variance.
+    ///
+    /// This is the same as `variance` and `variance_ddof(0)`, and is `0` if
+    /// there is no data.
+    pub fn population_variance(&self) -> f64 {
+        self.variance()
+    }
+
+    /// Return the current sample standard deviation.
+    ///
+    /// If the total weight is not greater than `1`, then this is `NaN`.
+    pub fn sample_stddev(&self) -> f64 {
+        self.sample_variance().sqrt()
+    }
+
+    /// Return the current sample variance.
+    ///
+    /// This is the unbiased estimator of the variance, which divides by
+    /// `weight - 1` instead of `weight`. If the total weight is not greater
+    /// than `1` (for unweighted samples, if there are fewer than two), then
+    /// this is `NaN`.
+    ///
+    /// Weighted samples are treated as frequency weights, i.e., a weight of
+    /// `3` is the same as adding the sample three times. So this divides by
+    /// `weight - 1`. See `reliability_variance` for the other kind.
+    pub fn sample_variance(&self) -> f64 {
+        self.variance_ddof(1)
+    }
+
+    /// Return the current unbiased variance for reliability weights.
+    ///
+    /// Reliability weights describe the importance of each sample rather
+    /// than how many times it occurred, so only their relative size
+    /// matters. This divides by `weight - weight_sq / weight`, where
+    /// `weight_sq` is the sum of the squared weights. Without weights, this
+    /// is the same as `sample_variance`.
+    ///
+    /// If the denominator is not positive, then this is `NaN`.
+    pub fn reliability_variance(&self) -> f64 {
+        if self.weight == 0.0 {
+            return f64::NAN;
+        }
+        let denom = self.weight - self.weight_sq / self.weight;
+        if denom <= 0.0 {
+            return f64::NAN;
+        }
+        self.m2 / denom
+    }
+
+    /// Return the current standard deviation with `ddof` delta degrees of
+    /// freedom.
+    pub fn stddev_ddof(&self, ddof: usize) -> f64 {
+        self.variance_ddof(ddof).sqrt()
+    }
+
+    /// Return the current variance with `ddof` delta degrees of freedom.
+    ///
+    /// The sum of squared differences from the mean is divided by
+    /// `weight - ddof`, where `weight` is the same as `len` when samples
+    /// aren't weighted. If `weight <= ddof`, then this is `NaN`, except
+    /// that `ddof = 0` is the population variance, which is `0` if there is
+    /// no data.
+    pub fn variance_ddof(&self, ddof: usize) -> f64 {
+        if ddof == 0 {
+            return self.variance();
+        }
+        if self.weight <= ddof as f64 {
+            return f64::NAN;
+        }
+        self.m2 / (self.weight - ddof as f64)
+    }
+
+    /// Return the current skewness.
+    ///
+    /// This is the population skewness `g1`. If the variance is `0`, then
+    /// the skewness is `NaN`.
+    pub fn skewness(&self) -> f64 {
+        self.weight.sqrt() * self.m3 / self.m2.powf(1.5)
+    }
+
+    /// Return the current excess kurtosis.
+    ///
+    /// This is the population excess kurtosis `g2`, which is `0` for a normal
+    /// distribution. If the variance is `0`, then the kurtosis is `NaN`.
+    pub fn kurtosis(&self) -> f64 {
+        self.weight * self.m4 / (self.m2 * self.m2) - 3.0
     }
 
     /// Add a new sample.
     pub fn add<T: ToPrimitive>(&mut self, sample: T) {
+        self.add_weighted(sample, 1.0);
+    }
+
+    /// Add a new sample with the given weight.
+    ///
+    /// The weight must be non-negative. Samples with a weight of `0` are
+    /// ignored, as they are by `MinMax` and `WeightedUnsorted`.
+    ///
+    /// # Panics
+    ///
+    /// If `weight` is negative, infinite or NaN.
+    pub fn add_weighted<T: ToPrimitive>(&mut self, sample: T, weight: f64) {
+        assert!(weight.is_finite() && weight >= 0.0,
+                "sample weight {} is negative or not finite", weight);
+        if weight == 0.0 {
+            return;
+        }
         let sample = sample.to_f64().unwrap();
+        self.size += 1;
         // Taken from: http://goo.gl/JKeqvj
         // See also: http://goo.gl/qTtI3V
-        let oldmean = self.mean;
-        let prevq = self.variance * (self.size as f64);
+        // This is `merge` specialized to a single sample, which reduces to
+        // the usual update when `weight` is `1`.
+        let w1 = self.weight;
+        self.weight += weight;
+        self.weight_sq += weight * weight;
+        let n = self.weight;
+        let delta = sample - self.mean;
+        let delta_n = delta * weight / n;
+        let delta_n2 = delta_n * delta_n;
+        let term1 = delta * delta_n * w1;
 
-        self.size += 1;
-        self.mean += (sample - oldmean) / (self.size as f64);
-        self.variance = (prevq + (sample - oldmean) * (sample - self.mean))
-                        / (self.size as f64);
+        self.mean += delta_n;
+        self.m4 += term1 * delta_n2
+                   * (w1 * w1 - w1 * weight + weight * weight)
+                   / (weight * weight)
+                   + 6.0 * delta_n2 * self.m2
+                   - 4.0 * delta_n * self.m3;
+        self.m3 += term1 * delta_n * (w1 - weight) / weight
+                   - 3.0 * delta_n * self.m2;
+        self.m2 += term1;
+    }
+
+    /// Remove a sample that was previously added with `add`.
+    ///
+    /// This undoes the effect of `add`, which makes it possible to maintain
+    /// statistics over a sliding window. Removing a sample that was never
+    /// added produces meaningless results.
+    ///
+    /// Each removal can add a little rounding error, so the statistics
+    /// slowly drift from those of the remaining samples. Windows with a very
+    /// long life may want to recompute them from scratch now and then.
+    pub fn remove<T: ToPrimitive>(&mut self, sample: T) {
+        self.remove_weighted(sample, 1.0);
+    }
+
+    /// Remove a sample that was previously added with `add_weighted` using
+    /// the same weight.
+    ///
+    /// Like adding, removing a sample with a weight of `0` does nothing.
+    ///
+    /// # Panics
+    ///
+    /// If `weight` is negative, infinite or NaN.
+    pub fn remove_weighted<T: ToPrimitive>(&mut self, sample: T, weight: f64) {
+        assert!(weight.is_finite() && weight >= 0.0,
+                "sample weight {} is negative or not finite", weight);
+        if weight == 0.0 {
+            return;
+        }
+        let sample = sample.to_f64().unwrap();
+        self.size = self.size.saturating_sub(1);
+        let n = self.weight;
+        let w1 = n - weight;
+        if w1 <= 0.0 {
+            *self = OnlineStats { size: self.size, ..OnlineStats::default() };
+            return;
+        }
+        // This solves the update in `add_weighted` for the state before the
+        // sample was added.
+        let mean = (n * self.mean - weight * sample) / w1;
+        let delta = sample - mean;
+        let delta2 = delta * delta;
+        let m2 = (self.m2 - delta2 * w1 * weight / n).max(0.0);
+        let m3 = self.m3
+                 - delta * delta2 * w1 * weight * (w1 - weight) / (n * n)
+                 + 3.0 * delta * weight * m2 / n;
+        let m4 = self.m4
+                 - delta2 * delta2 * w1 * weight
+                   * (w1 * w1 - w1 * weight + weight * weight) / (n * n * n)
+                 - 6.0 * delta2 * weight * weight * m2 / (n * n)
+                 + 4.0 * delta * weight * m3 / n;
+        self.weight = w1;
+        self.weight_sq -= weight * weight;
+        self.mean = mean;
+        self.m2 = m2;
+        self.m3 = m3;
+        self.m4 = m4;
     }
 
     /// Add a new NULL value to the population.
@@ -85,21 +302,58 @@ impl OnlineStats {
     pub fn len(&self) -> usize {
         self.size as usize
     }
+
+    /// Returns true if there are no data points.
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns the sum of the sample weights.
+    ///
+    /// This is the same as `len` when samples aren't weighted.
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
 }
 
 impl Commute for OnlineStats {
     fn merge(&mut self, v: OnlineStats) {
+        if v.weight == 0.0 {
+            self.size += v.size;
+            return;
+        }
+        if self.weight == 0.0 {
+            let size = self.size + v.size;
+            *self = v;
+            self.size = size;
+            return;
+        }
         // Taken from: http://goo.gl/iODi28
-        let (s1, s2) = (self.size as f64, v.size as f64);
-        let meandiffsq = (self.mean - v.mean) * (self.mean - v.mean);
-        let mean = ((s1 * self.mean) + (s2 * v.mean)) / (s1 + s2);
-        let var = (((s1 * self.variance) + (s2 * v.variance))
-                   / (s1 + s2))
-                  +
-                  ((s1 * s2 * meandiffsq) / ((s1 + s2) * (s1 + s2)));
+        // The 3rd and 4th moments are from Pébay (2008), "Formulas for
+        // Robust, One-Pass Parallel Computation of Covariances and
+        // Arbitrary-Order Statistical Moments".
+        let (s1, s2) = (self.weight, v.weight);
+        let n = s1 + s2;
+        let delta = v.mean - self.mean;
+        let delta2 = delta * delta;
+
+        let m2 = self.m2 + v.m2 + delta2 * s1 * s2 / n;
+        let m3 = self.m3 + v.m3
+                 + delta * delta2 * s1 * s2 * (s1 - s2) / (n * n)
+                 + 3.0 * delta * (s1 * v.m2 - s2 * self.m2) / n;
+        let m4 = self.m4 + v.m4
+                 + delta2 * delta2 * s1 * s2 * (s1 * s1 - s1 * s2 + s2 * s2)
+                   / (n * n * n)
+                 + 6.0 * delta2 * (s1 * s1 * v.m2 + s2 * s2 * self.m2)
+                   / (n * n)
+                 + 4.0 * delta * (s1 * v.m3 - s2 * self.m3) / n;
         self.size += v.size;
-        self.mean = mean;
-        self.variance = var;
+        self.weight = n;
+        self.weight_sq += v.weight_sq;
+        self.mean = ((s1 * self.mean) + (s2 * v.mean)) / n;
+        self.m2 = m2;
+        self.m3 = m3;
+        self.m4 = m4;
     }
 }
 
@@ -107,8 +361,12 @@ impl Default for OnlineStats {
     fn default() -> OnlineStats {
         OnlineStats {
             size: 0,
+            weight: 0.0,
+            weight_sq: 0.0,
             mean: 0.0,
-            variance: 0.0,
+            m2: 0.0,
+            m3: 0.0,
+            m4: 0.0,
         }
     }
 }
@@ -138,7 +396,8 @@ impl<T: ToPrimitive> Extend<T> for OnlineStats {
 #[cfg(test)]
 mod test {
     use {Commute, merge_all};
-    use super::OnlineStats;
+    use testutil::close;
+    use super::{OnlineStats, stddev_ddof, variance_ddof};
 
     #[test]
     fn stddev() {
@@ -166,4 +425,166 @@ mod test {
         assert_eq!(expected.stddev(),
                    merge_all(vars.into_iter()).unwrap().stddev());
     }
+
+    fn two_pass(xs: &[f64]) -> (f64, f64) {
+        let n = xs.len() as f64;
+        let mean = xs.iter().sum::<f64>() / n;
+        let moment = |k| {
+            xs.iter().map(|x| (x - mean).powi(k)).sum::<f64>() / n
+        };
+        let (m2, m3, m4) = (moment(2), moment(3), moment(4));
+        (m3 / m2.powf(1.5), m4 / (m2 * m2) - 3.0)
+    }
+
+    #[test]
+    fn skewness_kurtosis() {
+        let xs = [1.0, 2.0, 2.0, 3.0, 5.0, 8.0, 13.0, 21.0, 0.5, -4.0];
+        let (skew, kurt) = two_pass(&xs);
+        let got = OnlineStats::from_slice(&xs);
+        assert!((got.skewness() - skew).abs() < 1e-12);
+        assert!((got.kurtosis() - kurt).abs() < 1e-12);
+    }
+
+    #[test]
+    fn skewness_kurtosis_merge() {
+        let xs = [1.0, 2.0, 2.0, 3.0, 5.0, 8.0, 13.0, 21.0, 0.5, -4.0];
+        let (skew, kurt) = two_pass(&xs);
+        let vars = vec![
+            OnlineStats::new(),
+            OnlineStats::from_slice(&xs[..3]),
+            OnlineStats::from_slice(&xs[3..4]),
+            OnlineStats::from_slice(&xs[4..]),
+        ];
+        let got = merge_all(vars.into_iter()).unwrap();
+        assert_eq!(got.len(), xs.len());
+        assert!((got.skewness() - skew).abs() < 1e-12);
+        assert!((got.kurtosis() - kurt).abs() < 1e-12);
+    }
+
+    #[test]
+    fn sample_variance() {
+        let xs = [2usize, 4, 4, 4, 5, 5, 7, 9];
+        let stats = OnlineStats::from_slice(&xs);
+        assert!(close(stats.population_variance(), 4.0));
+        assert!(close(stats.population_stddev(), 2.0));
+        assert!(close(stats.sample_variance(), 32.0 / 7.0));
+        assert!(close(stats.sample_stddev(), (32.0f64 / 7.0).sqrt()));
+        assert!(close(variance_ddof(xs.iter().cloned(), 1), 32.0 / 7.0));
+        assert!(close(stddev_ddof(xs.iter().cloned(), 0), 2.0));
+
+        assert!(OnlineStats::from_slice(&[1usize]).sample_variance().is_nan());
+        let empty = OnlineStats::new();
+        assert_eq!(empty.variance_ddof(0), empty.population_variance());
+        assert_eq!(empty.variance_ddof(0), 0.0);
+        assert!(empty.variance_ddof(1).is_nan());
+    }
+
+    #[test]
+    fn sample_variance_merge() {
+        let mut got = OnlineStats::from_slice(&[2usize, 4, 4]);
+        got.merge(OnlineStats::from_slice(&[4usize, 5, 5, 7, 9]));
+        assert!((got.sample_variance() - 32.0 / 7.0).abs() < 1e-12);
+        assert!((got.population_variance() - 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn weighted_frequency() {
+        // Frequency weights are the same as repeating the sample.
+        let expected = OnlineStats::from_slice(&[1usize, 2, 2, 2, 5, 5]);
+        let mut got = OnlineStats::new();
+        got.add_weighted(1usize, 1.0);
+        got.add_weighted(2usize, 3.0);
+        got.add_weighted(5usize, 2.0);
+        got.add_weighted(100usize, 0.0);
+        assert_eq!(got.len(), 3);
+        assert_eq!(got.weight(), 6.0);
+        assert!(close(got.mean(), expected.mean()));
+        assert!(close(got.variance(), expected.variance()));
+        assert!(close(got.sample_variance(), expected.sample_variance()));
+        assert!(close(got.skewness(), expected.skewness()));
+        assert!(close(got.kurtosis(), expected.kurtosis()));
+    }
+
+    #[test]
+    fn weighted_zero() {
+        let mut got = OnlineStats::new();
+        got.add_weighted(100usize, 0.0);
+        assert!(got.is_empty());
+        got.add(3usize);
+        got.remove_weighted(7usize, 0.0);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got.mean(), 3.0);
+    }
+
+    #[test]
+    fn weighted_reliability() {
+        let (xs, ws) = ([1.0, 2.0, 5.0], [0.5, 0.25, 0.25]);
+        let mut got = OnlineStats::new();
+        for (&x, &w) in xs.iter().zip(&ws) {
+            got.add_weighted(x, w);
+        }
+        // mean = 2.25, sum(w * (x - mean)^2) = 2.6875, V2 = 0.375
+        assert!((got.mean() - 2.25).abs() < 1e-12);
+        assert!((got.reliability_variance() - 2.6875 / 0.625).abs() < 1e-12);
+
+        let unweighted = OnlineStats::from_slice(&xs);
+        assert!((unweighted.reliability_variance()
+                 - unweighted.sample_variance()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn weighted_merge() {
+        let mut expected = OnlineStats::new();
+        let mut v1 = OnlineStats::new();
+        let mut v2 = OnlineStats::new();
+        for (i, &(x, w)) in [(1.0, 0.5), (3.0, 2.0), (4.0, 1.5), (9.0, 0.25),
+                             (2.0, 3.0)].iter().enumerate() {
+            expected.add_weighted(x, w);
+            if i % 2 == 0 {
+                v1.add_weighted(x, w);
+            } else {
+                v2.add_weighted(x, w);
+            }
+        }
+        v1.merge(v2);
+        assert_eq!(v1.len(), expected.len());
+        assert!(close(v1.mean(), expected.mean()));
+        assert!(close(v1.variance(), expected.variance()));
+        assert!(close(v1.reliability_variance(),
+                      expected.reliability_variance()));
+        assert!(close(v1.skewness(), expected.skewness()));
+        assert!(close(v1.kurtosis(), expected.kurtosis()));
+    }
+
+    #[test]
+    fn remove() {
+        let xs = [3.0, 1.0, 4.0, 1.0, 5.0, 9.0, 2.0, 6.0];
+        let mut got = OnlineStats::from_slice(&xs);
+        got.remove(3.0);
+        got.remove(1.0);
+        got.add_weighted(7.0, 2.5);
+        let expected = {
+            let mut v = OnlineStats::from_slice(&xs[2..]);
+            v.add_weighted(7.0, 2.5);
+            v
+        };
+        assert_eq!(got.len(), expected.len());
+        assert!(close(got.mean(), expected.mean()));
+        assert!(close(got.variance(), expected.variance()));
+        assert!(close(got.skewness(), expected.skewness()));
+        assert!(close(got.kurtosis(), expected.kurtosis()));
+
+        got.remove_weighted(7.0, 2.5);
+        let expected = OnlineStats::from_slice(&xs[2..]);
+        assert!(close(got.mean(), expected.mean()));
+        assert!(close(got.reliability_variance(),
+                      expected.reliability_variance()));
+
+        for &x in &xs[2..] {
+            got.remove(x);
+        }
+        assert!(got.is_empty());
+        assert_eq!(got.mean(), 0.0);
+        assert_eq!(got.variance(), 0.0);
+    }
 }
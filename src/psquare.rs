@@ -0,0 +1,352 @@
+use std::cmp::Ordering;
+use std::default::Default;
+use std::iter::{FromIterator, IntoIterator};
+
+use num::ToPrimitive;
+
+use Commute;
+
+/// An estimator for quantiles in constant memory.
+///
+/// This is the P² algorithm of Jain and Chlamtac (1985), extended to track
+/// several quantiles at once as described by Raatikainen (1987). Each
+/// tracked quantile costs two markers, plus three shared ones, and every
+/// sample moves the markers toward their ideal ranks along a parabola
+/// through their neighbours. Nothing else about the data is stored.
+///
+/// The estimates are not guaranteed to be within any bound, but they are
+/// usually very good for smooth distributions.
+///
+/// Markers can't be merged without losing information, so this type does
+/// not implement `Commute`. An approximate merge is available by wrapping
+/// estimators in `ApproxPSquare`.
+#[derive(Clone, Debug)]
+pub struct PSquare {
+    ps: Vec<f64>,
+    count: u64,
+    // The quantile each marker ideally sits at.
+    desired: Vec<f64>,
+    // Until there is one sample for every marker, this holds the samples
+    // themselves in sorted order.
+    heights: Vec<f64>,
+    // 1-based ranks of each marker.
+    positions: Vec<f64>,
+}
+
+impl PSquare {
+    /// Create an estimator for the quantile `p`, where `p` is in `[0, 1]`.
+    pub fn new(p: f64) -> PSquare {
+        PSquare::with_quantiles(&[p])
+    }
+
+    /// Create an estimator for each of the quantiles in `ps`, which must
+    /// be in `[0, 1]`.
+    pub fn with_quantiles(ps: &[f64]) -> PSquare {
+        assert!(!ps.is_empty(), "at least one quantile must be tracked");
+        let mut ps = ps.to_vec();
+        for &p in &ps {
+            assert!((0.0..=1.0).contains(&p),
+                    "quantile {} is not in [0, 1]", p);
+        }
+        ps.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        ps.dedup();
+
+        // Markers sit at the extremes, at every quantile and halfway
+        // between each pair of neighbours.
+        let mut desired = vec![0.0];
+        let mut prev = 0.0;
+        for &p in ps.iter().chain(Some(&1.0)) {
+            desired.push((prev + p) / 2.0);
+            desired.push(p);
+            prev = p;
+        }
+        desired.dedup();
+        PSquare {
+            ps,
+            count: 0,
+            heights: Vec::with_capacity(desired.len()),
+            positions: vec![],
+            desired,
+        }
+    }
+
+    /// Add a sample to the estimator.
+    ///
+    /// `NaN` samples are ignored.
+    pub fn add<T: ToPrimitive>(&mut self, sample: T) {
+        let x = sample.to_f64().unwrap();
+        if x.is_nan() {
+            return;
+        }
+        self.count += 1;
+        let n = self.desired.len();
+        if self.positions.is_empty() {
+            let i = self.heights.partition_point(|&h| h <= x);
+            self.heights.insert(i, x);
+            if self.heights.len() == n {
+                self.positions = (1..n + 1).map(|i| i as f64).collect();
+            }
+            return;
+        }
+
+        let h = &mut self.heights;
+        let k = if x < h[0] {
+            h[0] = x;
+            0
+        } else if x >= h[n - 1] {
+            h[n - 1] = x;
+            n - 2
+        } else {
+            h.partition_point(|&v| v <= x) - 1
+        };
+        for pos in &mut self.positions[k + 1..] {
+            *pos += 1.0;
+        }
+        for i in 1..n - 1 {
+            let target = 1.0 + (self.count - 1) as f64 * self.desired[i];
+            let d = target - self.positions[i];
+            let pos = &self.positions;
+            if (d >= 1.0 && pos[i + 1] - pos[i] > 1.0)
+                    || (d <= -1.0 && pos[i - 1] - pos[i] < -1.0) {
+                let s = d.signum();
+                self.heights[i] = self.adjusted(i, s);
+                self.positions[i] += s;
+            }
+        }
+    }
+
+    /// Returns the new height of marker `i` after moving it by `s`, which
+    /// is either `1` or `-1`.
+    fn adjusted(&self, i: usize, s: f64) -> f64 {
+        let (h, pos) = (&self.heights, &self.positions);
+        let parabolic = h[i] + s / (pos[i + 1] - pos[i - 1])
+            * ((pos[i] - pos[i - 1] + s) * (h[i + 1] - h[i])
+                   / (pos[i + 1] - pos[i])
+               + (pos[i + 1] - pos[i] - s) * (h[i] - h[i - 1])
+                   / (pos[i] - pos[i - 1]));
+        if h[i - 1] < parabolic && parabolic < h[i + 1] {
+            return parabolic;
+        }
+        // Fall back to linear interpolation when the parabola would break
+        // the ordering of the markers.
+        let j = if s > 0.0 { i + 1 } else { i - 1 };
+        h[i] + s * (h[j] - h[i]) / (pos[j] - pos[i])
+    }
+
+    /// Returns the number of samples added.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Returns true if no samples have been added.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the estimate of the tracked quantile `p`.
+    ///
+    /// Until there are enough samples to place every marker, this is the
+    /// exact quantile, linearly interpolated. `None` is returned if and
+    /// only if there is no data.
+    ///
+    /// # Panics
+    ///
+    /// If `p` is not one of the tracked quantiles.
+    pub fn quantile(&self, p: f64) -> Option<f64> {
+        assert!(self.ps.contains(&p), "quantile {} is not tracked", p);
+        if self.count == 0 {
+            return None;
+        }
+        if self.positions.is_empty() {
+            let rank = p * (self.heights.len() - 1) as f64;
+            let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+            let (a, b) = (self.heights[lo], self.heights[hi]);
+            return Some(a + (rank - lo as f64) * (b - a));
+        }
+        let i = self.desired.iter().position(|&q| q == p).unwrap();
+        Some(self.heights[i])
+    }
+
+    /// Returns the estimates of all tracked quantiles, in ascending order
+    /// of the quantiles.
+    pub fn quantiles(&self) -> Option<Vec<f64>> {
+        self.ps.iter().map(|&p| self.quantile(p)).collect()
+    }
+
+    /// Returns the approximate number of samples at most `x`, by linear
+    /// interpolation between markers.
+    fn rank(&self, x: f64) -> f64 {
+        let (h, pos) = (&self.heights, &self.positions);
+        if x < h[0] {
+            return 0.0;
+        }
+        if x >= h[h.len() - 1] {
+            return self.count as f64;
+        }
+        let j = h.partition_point(|&v| v <= x) - 1;
+        pos[j] + (x - h[j]) / (h[j + 1] - h[j]) * (pos[j + 1] - pos[j])
+    }
+}
+
+impl Default for PSquare {
+    /// Create an estimator for the median.
+    fn default() -> PSquare {
+        PSquare::new(0.5)
+    }
+}
+
+impl<T: ToPrimitive> FromIterator<T> for PSquare {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> PSquare {
+        let mut v = PSquare::default();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: ToPrimitive> Extend<T> for PSquare {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+/// A `PSquare` estimator with an approximate `Commute` implementation.
+///
+/// Merging rebuilds the markers from the ranks that both sets of markers
+/// imply, assuming the data is spread linearly between markers. The result
+/// is a reasonable estimate, but unlike the other types in this crate it
+/// depends on how the data was split, and errors compound with every
+/// merge. Only estimators tracking the same quantiles can be merged.
+#[derive(Clone, Debug, Default)]
+pub struct ApproxPSquare(pub PSquare);
+
+impl Commute for ApproxPSquare {
+    fn merge(&mut self, v: ApproxPSquare) {
+        let (a, b) = (&mut self.0, v.0);
+        if b.is_empty() {
+            return;
+        }
+        if a.is_empty() {
+            *a = b;
+            return;
+        }
+        assert!(a.ps == b.ps,
+                "cannot merge estimators of different quantiles");
+        // Samples that are still held exactly can simply be added.
+        if b.positions.is_empty() {
+            a.extend(b.heights);
+            return;
+        }
+        if a.positions.is_empty() {
+            let samples = ::std::mem::replace(a, b).heights;
+            a.extend(samples);
+            return;
+        }
+
+        let n = a.desired.len();
+        let total = a.count + b.count;
+        let mut xs: Vec<f64> =
+            a.heights.iter().chain(&b.heights).cloned().collect();
+        xs.sort_by(|x, y| x.partial_cmp(y).unwrap_or(Ordering::Equal));
+        let ranks: Vec<f64> = xs.iter().map(|&x| a.rank(x) + b.rank(x))
+                                       .collect();
+
+        let mut heights = Vec::with_capacity(n);
+        let mut positions = Vec::with_capacity(n);
+        for (i, &q) in a.desired.iter().enumerate() {
+            let target = 1.0 + (total - 1) as f64 * q;
+            // Marker positions must be distinct ranks, leaving room for
+            // the markers on either side.
+            let lo = positions.last().map_or(1.0, |&p: &f64| p + 1.0);
+            let hi = (total - (n - 1 - i) as u64) as f64;
+            let pos = target.round().max(lo).min(hi);
+            let j = ranks.partition_point(|&r| r < pos).min(xs.len() - 1);
+            let height = if j == 0 || ranks[j] == ranks[j - 1] {
+                xs[j]
+            } else {
+                xs[j - 1] + (pos - ranks[j - 1]) / (ranks[j] - ranks[j - 1])
+                            * (xs[j] - xs[j - 1])
+            };
+            heights.push(height);
+            positions.push(pos);
+        }
+        heights[0] = xs[0];
+        heights[n - 1] = xs[xs.len() - 1];
+        a.count = total;
+        a.heights = heights;
+        a.positions = positions;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use testutil::shuffled;
+    use super::{ApproxPSquare, PSquare};
+
+    #[test]
+    fn median() {
+        let n = 100_000;
+        let est: PSquare = shuffled(n).into_iter().collect();
+        assert_eq!(est.len(), n as usize);
+        let got = est.quantile(0.5).unwrap();
+        assert!((got - 50_000.0).abs() / n as f64 <= 0.005, "got {}", got);
+    }
+
+    #[test]
+    fn several() {
+        let n = 100_000;
+        let ps = [0.1, 0.5, 0.9, 0.99];
+        let mut est = PSquare::with_quantiles(&ps);
+        assert_eq!(est.desired.len(), 2 * ps.len() + 3);
+        est.extend(shuffled(n));
+        let got = est.quantiles().unwrap();
+        for (&p, &q) in ps.iter().zip(&got) {
+            let expected = p * n as f64;
+            assert!((q - expected).abs() / n as f64 <= 0.005,
+                    "p = {}: expected {}, got {}", p, expected, q);
+        }
+    }
+
+    #[test]
+    fn few_samples() {
+        let mut est = PSquare::new(0.5);
+        assert_eq!(est.quantile(0.5), None);
+        est.extend(vec![4, 1, 3]);
+        assert_eq!(est.quantile(0.5), Some(3.0));
+        est.add(2);
+        assert_eq!(est.quantile(0.5), Some(2.5));
+    }
+
+    #[test]
+    #[should_panic]
+    fn untracked() {
+        let est: PSquare = vec![1, 2, 3].into_iter().collect();
+        est.quantile(0.25);
+    }
+
+    #[test]
+    fn approx_merge() {
+        let n = 100_000;
+        let xs = shuffled(n);
+        let ests = xs.chunks(9_973).map(|chunk| {
+            let mut est = PSquare::with_quantiles(&[0.5, 0.9]);
+            est.extend(chunk.iter().cloned());
+            ApproxPSquare(est)
+        });
+        let mut merged = merge_all(ests).unwrap();
+        merged.merge(ApproxPSquare::default());
+        assert_eq!(merged.0.len(), n as usize);
+        for &p in &[0.5, 0.9] {
+            let got = merged.0.quantile(p).unwrap();
+            let expected = p * n as f64;
+            assert!((got - expected).abs() / n as f64 <= 0.02,
+                    "p = {}: expected {}, got {}", p, expected, got);
+        }
+
+        let mut small = ApproxPSquare(vec![1, 2].into_iter().collect());
+        small.merge(ApproxPSquare(vec![3, 4, 5].into_iter().collect()));
+        assert_eq!(small.0.quantile(0.5), Some(3.0));
+    }
+}
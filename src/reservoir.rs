@@ -0,0 +1,485 @@
+use std::default::Default;
+use std::iter::{FromIterator, IntoIterator};
+
+use {Commute, Unsorted};
+use rng::Rng;
+
+/// The algorithm a `Reservoir` uses to decide which samples to keep.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReservoirAlgorithm {
+    /// Vitter's Algorithm R, which draws a random number for every sample.
+    R,
+    /// Li's Algorithm L, which draws random numbers only for the samples
+    /// it keeps by skipping ahead over the ones it doesn't.
+    L,
+}
+
+/// A commutative uniform random sample of a stream in bounded memory.
+///
+/// Once more than `capacity` samples have been added, every sample is in
+/// the reservoir with the same probability. Merging keeps that true of the
+/// combined stream, so a sample can be taken in parts and put together
+/// with `merge_all`.
+///
+/// `into_unsorted` turns the sample into an `Unsorted`, whose median,
+/// quantiles and mode then estimate those of the whole stream.
+#[derive(Clone, Debug)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    algorithm: ReservoirAlgorithm,
+    seen: u64,
+    sample: Vec<T>,
+    rng: Rng,
+    // For Algorithm L, the largest of the random keys of the kept samples
+    // and the number of samples seen when the next one will be kept.
+    w: f64,
+    next: u64,
+}
+
+impl<T> Reservoir<T> {
+    /// Create an empty reservoir for at most `capacity` samples, which
+    /// uses Algorithm L seeded from the environment.
+    pub fn with_capacity(capacity: usize) -> Reservoir<T> {
+        let seed = Rng::from_entropy().next_u64();
+        Reservoir::with_seed(capacity, ReservoirAlgorithm::L, seed)
+    }
+
+    /// Create an empty reservoir for at most `capacity` samples, which
+    /// uses `algorithm` seeded by `seed`.
+    ///
+    /// The same seed and the same data always produce the same sample.
+    pub fn with_seed(capacity: usize, algorithm: ReservoirAlgorithm,
+                     seed: u64) -> Reservoir<T> {
+        assert!(capacity >= 1, "capacity must be at least 1");
+        Reservoir {
+            capacity,
+            algorithm,
+            seen: 0,
+            sample: Vec::with_capacity(capacity),
+            rng: Rng::new(seed),
+            w: 0.0,
+            next: 0,
+        }
+    }
+
+    /// Add a sample to the stream.
+    pub fn add(&mut self, v: T) {
+        self.seen += 1;
+        if self.sample.len() < self.capacity {
+            self.sample.push(v);
+            if self.sample.len() == self.capacity {
+                self.w = (self.rng.next_f64().ln() / self.capacity as f64)
+                         .exp();
+                self.skip();
+            }
+            return;
+        }
+        match self.algorithm {
+            ReservoirAlgorithm::R => {
+                let i = self.rng.below(self.seen) as usize;
+                if i < self.capacity {
+                    self.sample[i] = v;
+                }
+            }
+            ReservoirAlgorithm::L => {
+                if self.seen == self.next {
+                    let i = self.rng.below(self.capacity as u64) as usize;
+                    self.sample[i] = v;
+                    self.w *= (self.rng.next_f64().ln()
+                               / self.capacity as f64).exp();
+                    self.skip();
+                }
+            }
+        }
+    }
+
+    /// Picks the next sample Algorithm L will keep.
+    fn skip(&mut self) {
+        let gap = self.rng.next_f64().ln() / (1.0 - self.w).ln();
+        let gap = gap.floor().min(u64::MAX as f64 / 2.0) as u64;
+        self.next = self.seen + gap + 1;
+    }
+
+    /// Returns the samples in the reservoir, in no particular order.
+    pub fn sample(&self) -> &[T] {
+        &self.sample
+    }
+
+    /// Returns the number of samples in the reservoir.
+    pub fn len(&self) -> usize {
+        self.sample.len()
+    }
+
+    /// Returns true if the reservoir is empty.
+    pub fn is_empty(&self) -> bool {
+        self.sample.is_empty()
+    }
+
+    /// Returns the number of samples added to the stream.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    /// Returns the maximum number of samples kept.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn shuffle(&mut self) {
+        for i in (1..self.sample.len()).rev() {
+            let j = self.rng.below(i as u64 + 1) as usize;
+            self.sample.swap(i, j);
+        }
+    }
+}
+
+impl<T: PartialOrd> Reservoir<T> {
+    /// Returns the sample as an `Unsorted`.
+    pub fn into_unsorted(self) -> Unsorted<T> {
+        self.sample.into_iter().collect()
+    }
+}
+
+impl<T> Commute for Reservoir<T> {
+    fn merge(&mut self, mut v: Reservoir<T>) {
+        if v.seen == 0 {
+            return;
+        }
+        if self.seen == 0 {
+            *self = v;
+            return;
+        }
+        let capacity = self.capacity.min(v.capacity);
+        let seen = self.seen + v.seen;
+        self.shuffle();
+        v.shuffle();
+        // Draw without replacement from both streams, where each draw
+        // picks a stream in proportion to what's left of it. Since both
+        // samples are uniform and shuffled, taking the next sample of the
+        // picked stream is the same as picking one of its samples at
+        // random.
+        let (mut left_a, mut left_b) = (self.seen, v.seen);
+        let mut a = ::std::mem::take(&mut self.sample).into_iter();
+        let mut b = v.sample.into_iter();
+        let mut sample = Vec::with_capacity(capacity);
+        while sample.len() < capacity && left_a + left_b > 0 {
+            let from_a = self.rng.below(left_a + left_b) < left_a;
+            let next = if from_a {
+                left_a -= 1;
+                a.next()
+            } else {
+                left_b -= 1;
+                b.next()
+            };
+            sample.extend(next);
+        }
+        self.sample = sample;
+        self.capacity = capacity;
+        self.seen = seen;
+        if self.sample.len() == capacity {
+            // Algorithm L needs the largest of the `capacity` smallest of
+            // `seen` random keys, as if it had seen the whole stream.
+            let mut w = 0.0f64;
+            for j in 0..capacity as u64 {
+                let rest = (seen - j) as f64;
+                w += (1.0 - w) * (1.0 - self.rng.next_f64().powf(1.0 / rest));
+            }
+            self.w = w;
+            self.skip();
+        }
+    }
+}
+
+impl<T> Default for Reservoir<T> {
+    fn default() -> Reservoir<T> {
+        Reservoir::with_capacity(1000)
+    }
+}
+
+impl<T> FromIterator<T> for Reservoir<T> {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> Reservoir<T> {
+        let mut v = Reservoir::default();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T> Extend<T> for Reservoir<T> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+/// A commutative weighted random sample of a stream in bounded memory.
+///
+/// This is the A-Res scheme of Efraimidis and Spirakis (2006): every sample
+/// gets a random key `u^(1/weight)` and the samples with the largest keys
+/// are kept, so heavier samples are more likely to be kept. Samples are
+/// added with the A-ExpJ variant, which skips ahead over the samples it
+/// won't keep by weight. Since keys don't depend on the rest of the stream,
+/// merging keeps the largest keys of both reservoirs.
+#[derive(Clone, Debug)]
+pub struct WeightedReservoir<T> {
+    capacity: usize,
+    seen: u64,
+    sample: Vec<T>,
+    // The logarithm of the key of each sample.
+    keys: Vec<f64>,
+    rng: Rng,
+    // The weight left to skip before the next sample is kept.
+    skip: f64,
+}
+
+impl<T> WeightedReservoir<T> {
+    /// Create an empty reservoir for at most `capacity` samples, seeded
+    /// from the environment.
+    pub fn with_capacity(capacity: usize) -> WeightedReservoir<T> {
+        let seed = Rng::from_entropy().next_u64();
+        WeightedReservoir::with_seed(capacity, seed)
+    }
+
+    /// Create an empty reservoir for at most `capacity` samples, seeded by
+    /// `seed`.
+    pub fn with_seed(capacity: usize, seed: u64) -> WeightedReservoir<T> {
+        assert!(capacity >= 1, "capacity must be at least 1");
+        WeightedReservoir {
+            capacity,
+            seen: 0,
+            sample: Vec::with_capacity(capacity),
+            keys: Vec::with_capacity(capacity),
+            rng: Rng::new(seed),
+            skip: 0.0,
+        }
+    }
+
+    /// Add a sample to the stream with the given weight.
+    ///
+    /// Samples with a weight of `0` are never kept.
+    pub fn add(&mut self, v: T, weight: f64) {
+        assert!(weight >= 0.0, "sample weight {} is negative", weight);
+        if weight == 0.0 {
+            return;
+        }
+        self.seen += 1;
+        if self.sample.len() < self.capacity {
+            self.keys.push(self.rng.next_f64().ln() / weight);
+            self.sample.push(v);
+            if self.sample.len() == self.capacity {
+                self.reset_skip();
+            }
+            return;
+        }
+        self.skip -= weight;
+        if self.skip <= 0.0 {
+            // The new key is conditioned on beating the smallest one.
+            let (i, threshold) = self.min_key();
+            let t = (weight * threshold).exp();
+            let r = t + (1.0 - t) * self.rng.next_f64();
+            self.keys[i] = r.ln() / weight;
+            self.sample[i] = v;
+            self.reset_skip();
+        }
+    }
+
+    fn min_key(&self) -> (usize, f64) {
+        let mut min = (0, f64::INFINITY);
+        for (i, &key) in self.keys.iter().enumerate() {
+            if key < min.1 {
+                min = (i, key);
+            }
+        }
+        min
+    }
+
+    fn reset_skip(&mut self) {
+        let (_, threshold) = self.min_key();
+        self.skip = self.rng.next_f64().ln() / threshold;
+    }
+
+    /// Returns the samples in the reservoir, in no particular order.
+    pub fn sample(&self) -> &[T] {
+        &self.sample
+    }
+
+    /// Returns the number of samples in the reservoir.
+    pub fn len(&self) -> usize {
+        self.sample.len()
+    }
+
+    /// Returns true if the reservoir is empty.
+    pub fn is_empty(&self) -> bool {
+        self.sample.is_empty()
+    }
+
+    /// Returns the number of samples with a positive weight added to the
+    /// stream.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+}
+
+impl<T: PartialOrd> WeightedReservoir<T> {
+    /// Returns the sample as an `Unsorted`.
+    pub fn into_unsorted(self) -> Unsorted<T> {
+        self.sample.into_iter().collect()
+    }
+}
+
+impl<T> Commute for WeightedReservoir<T> {
+    fn merge(&mut self, v: WeightedReservoir<T>) {
+        if v.seen == 0 {
+            return;
+        }
+        if self.seen == 0 {
+            *self = v;
+            return;
+        }
+        self.capacity = self.capacity.min(v.capacity);
+        self.seen += v.seen;
+        let mut both: Vec<(f64, T)> =
+            self.keys.drain(..).zip(self.sample.drain(..))
+                .chain(v.keys.into_iter().zip(v.sample))
+                .collect();
+        both.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        both.truncate(self.capacity);
+        for (key, v) in both {
+            self.keys.push(key);
+            self.sample.push(v);
+        }
+        if self.sample.len() == self.capacity {
+            self.reset_skip();
+        }
+    }
+}
+
+impl<T> Default for WeightedReservoir<T> {
+    fn default() -> WeightedReservoir<T> {
+        WeightedReservoir::with_capacity(1000)
+    }
+}
+
+impl<T> FromIterator<(T, f64)> for WeightedReservoir<T> {
+    fn from_iter<I>(it: I) -> WeightedReservoir<T>
+            where I: IntoIterator<Item=(T, f64)> {
+        let mut v = WeightedReservoir::default();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T> Extend<(T, f64)> for WeightedReservoir<T> {
+    fn extend<I: IntoIterator<Item=(T, f64)>>(&mut self, it: I) {
+        for (sample, weight) in it {
+            self.add(sample, weight);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use Commute;
+    use super::{Reservoir, ReservoirAlgorithm, WeightedReservoir};
+
+    // Counts how often each of `0..n` ends up in the sample over many
+    // trials, and checks that every count is close to its expectation.
+    fn check_uniform<F>(n: usize, k: usize, mut trial: F)
+            where F: FnMut(u64) -> Vec<usize> {
+        let trials = 4000;
+        let mut counts = vec![0usize; n];
+        for seed in 0..trials {
+            let sample = trial(seed);
+            assert_eq!(sample.len(), k);
+            for v in sample {
+                counts[v] += 1;
+            }
+        }
+        let expected = (trials as usize * k / n) as f64;
+        for (v, &count) in counts.iter().enumerate() {
+            // Five standard deviations of a binomial count.
+            let sd = (expected * (1.0 - k as f64 / n as f64)).sqrt();
+            assert!((count as f64 - expected).abs() < 5.0 * sd,
+                    "{} sampled {} times, expected {}", v, count, expected);
+        }
+    }
+
+    #[test]
+    fn uniform() {
+        for &alg in &[ReservoirAlgorithm::R, ReservoirAlgorithm::L] {
+            check_uniform(100, 10, |seed| {
+                let mut r = Reservoir::with_seed(10, alg, seed);
+                r.extend(0..100);
+                assert_eq!(r.seen(), 100);
+                r.sample().to_vec()
+            });
+        }
+    }
+
+    #[test]
+    fn small() {
+        let r: Reservoir<i32> = vec![3, 1, 2].into_iter().collect();
+        let mut sample = r.sample().to_vec();
+        sample.sort();
+        assert_eq!(sample, vec![1, 2, 3]);
+        assert_eq!(r.into_unsorted().median(), Some(2.0));
+    }
+
+    #[test]
+    fn merge_uniform() {
+        check_uniform(100, 10, |seed| {
+            let mut a = Reservoir::with_seed(10, ReservoirAlgorithm::L, seed);
+            a.extend(0..30);
+            let mut b = Reservoir::with_seed(10, ReservoirAlgorithm::R,
+                                             seed + 1_000_000);
+            b.extend(30..100);
+            a.merge(b);
+            a.merge(Reservoir::with_capacity(10));
+            assert_eq!(a.seen(), 100);
+            a.sample().to_vec()
+        });
+    }
+
+    #[test]
+    fn merge_then_add() {
+        check_uniform(100, 10, |seed| {
+            let mut a = Reservoir::with_seed(10, ReservoirAlgorithm::L, seed);
+            a.extend(0..20);
+            let mut b = Reservoir::with_seed(10, ReservoirAlgorithm::L,
+                                             seed + 1_000_000);
+            b.extend(20..40);
+            a.merge(b);
+            a.extend(40..100);
+            a.sample().to_vec()
+        });
+    }
+
+    #[test]
+    fn weighted() {
+        let (mut heavy, trials) = (0, 4000);
+        for seed in 0..trials {
+            let mut r = WeightedReservoir::with_seed(1, seed);
+            r.extend(vec![(0, 1.0), (1, 9.0), (2, 0.0)]);
+            assert_eq!(r.seen(), 2);
+            if r.sample()[0] == 1 {
+                heavy += 1;
+            }
+        }
+        let p = heavy as f64 / trials as f64;
+        assert!((p - 0.9).abs() < 0.03, "heavy sampled {}", p);
+    }
+
+    #[test]
+    fn weighted_merge() {
+        // Equal weights make a weighted sample uniform.
+        check_uniform(100, 10, |seed| {
+            let mut a = WeightedReservoir::with_seed(10, seed);
+            a.extend((0..50).map(|v| (v, 2.0)));
+            let mut b = WeightedReservoir::with_seed(10, seed + 1_000_000);
+            b.extend((50..100).map(|v| (v, 2.0)));
+            a.merge(b);
+            a.merge(WeightedReservoir::with_capacity(10));
+            a.sample().to_vec()
+        });
+    }
+}
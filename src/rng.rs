@@ -0,0 +1,55 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// A small, fast and seedable pseudo-random number generator.
+///
+/// This is xorshift64* seeded through splitmix64. It is plenty for the
+/// randomized algorithms in this crate, but it must never be used for
+/// anything security related.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Create a generator from a seed. Equal seeds produce equal sequences.
+    pub fn new(seed: u64) -> Rng {
+        // splitmix64, which makes sure the state is never zero and that
+        // similar seeds produce unrelated sequences.
+        let mut z = seed.wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        Rng { state: if z == 0 { 0x9e3779b97f4a7c15 } else { z } }
+    }
+
+    /// Create a generator with a seed that differs every time.
+    pub fn from_entropy() -> Rng {
+        // The standard library randomly keys every `RandomState`, which is
+        // the only source of entropy available without a dependency.
+        Rng::new(RandomState::new().build_hasher().finish())
+    }
+
+    /// Returns a uniformly distributed `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Returns a uniformly distributed `f64` in `(0, 1]`.
+    pub fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a uniformly distributed integer in `[0, n)`.
+    pub fn below(&mut self, n: u64) -> u64 {
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// Returns a uniformly distributed `bool`.
+    pub fn next_bool(&mut self) -> bool {
+        self.next_u64() >> 63 == 1
+    }
+}
@@ -0,0 +1,342 @@
+use std::cmp::Reverse;
+use std::collections::hash_map::{HashMap, Entry};
+use std::default::Default;
+use std::hash::Hash;
+use std::iter::{FromIterator, IntoIterator};
+
+use Commute;
+
+/// A commutative summary of the most frequent values in bounded memory.
+///
+/// This is the Space-Saving algorithm of Metwally, Agrawal and El Abbadi
+/// (2005). At most `capacity` values are counted. When a new value arrives
+/// and every counter is taken, it replaces the value with the smallest
+/// count and inherits that count as its error. Counters are kept in a
+/// min-heap, so adding a sample takes `O(log capacity)` time.
+///
+/// Every count is reported as a range: at least `guaranteed` and at most
+/// `upper` occurrences. Any value that occurs more than
+/// `total() / capacity` times is always tracked.
+///
+/// Merging follows the parallel Space-Saving of Cafaro, Pulimeno and
+/// Tempesta (2016): a value missing from one summary is counted as that
+/// summary's smallest count, and the largest counts are kept. Upper bounds
+/// still overestimate by at most `total() / capacity`, with the smaller of
+/// the two capacities.
+#[derive(Clone, Debug)]
+pub struct SpaceSaving<T> {
+    capacity: usize,
+    total: u64,
+    // Maps each tracked value to the index of its counter in `heap`.
+    counters: HashMap<T, usize>,
+    // A min-heap of counters ordered by count.
+    heap: Vec<Counter<T>>,
+}
+
+#[derive(Clone, Debug)]
+struct Counter<T> {
+    value: T,
+    count: u64,
+    error: u64,
+}
+
+impl<T: Eq + Hash + Clone> SpaceSaving<T> {
+    /// Create an empty summary with the default capacity of `100`.
+    pub fn new() -> SpaceSaving<T> {
+        Default::default()
+    }
+
+    /// Create an empty summary that tracks at most `capacity` values.
+    pub fn with_capacity(capacity: usize) -> SpaceSaving<T> {
+        assert!(capacity >= 1, "capacity must be at least 1");
+        SpaceSaving {
+            capacity,
+            total: 0,
+            counters: HashMap::with_capacity(capacity),
+            heap: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Add a sample to the summary.
+    pub fn add(&mut self, v: T) {
+        self.total += 1;
+        if let Some(&i) = self.counters.get(&v) {
+            self.heap[i].count += 1;
+            self.sift_down(i);
+            return;
+        }
+        if self.heap.len() < self.capacity {
+            self.push(Counter { value: v, count: 1, error: 0 });
+            return;
+        }
+        let count = self.heap[0].count;
+        self.counters.remove(&self.heap[0].value);
+        self.counters.insert(v.clone(), 0);
+        self.heap[0] = Counter { value: v, count: count + 1, error: count };
+        self.sift_down(0);
+    }
+
+    /// Returns the lower and upper bounds on the number of occurrences of
+    /// `v` in the data.
+    pub fn bounds(&self, v: &T) -> (u64, u64) {
+        match self.counters.get(v) {
+            Some(&i) => {
+                let c = &self.heap[i];
+                (c.count - c.error, c.count)
+            }
+            None => (0, self.min_count()),
+        }
+    }
+
+    /// Returns the tracked values in descending order of their upper
+    /// bounds, as `(value, guaranteed, upper)`.
+    pub fn most_frequent(&self) -> Vec<(&T, u64, u64)> {
+        let mut counts: Vec<_> = self.heap.iter().map(|c| {
+            (&c.value, c.count - c.error, c.count)
+        }).collect();
+        counts.sort_by_key(|&(_, lower, upper)| {
+            (Reverse(upper), Reverse(lower))
+        });
+        counts
+    }
+
+    /// Returns the `k` values with the largest upper bounds, as
+    /// `(value, guaranteed, upper)`.
+    pub fn top(&self, k: usize) -> Vec<(&T, u64, u64)> {
+        let mut counts = self.most_frequent();
+        counts.truncate(k);
+        counts
+    }
+
+    /// Returns the number of samples added.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Returns the maximum number of values tracked.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of values tracked.
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Returns true if no samples have been added.
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// Returns the largest possible count of a value that isn't tracked.
+    fn min_count(&self) -> u64 {
+        if self.heap.len() < self.capacity {
+            return 0;
+        }
+        self.heap[0].count
+    }
+
+    fn push(&mut self, c: Counter<T>) {
+        self.counters.insert(c.value.clone(), self.heap.len());
+        self.heap.push(c);
+        let last = self.heap.len() - 1;
+        self.sift_up(last);
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+            if self.heap[parent].count <= self.heap[i].count {
+                break;
+            }
+            self.swap(i, parent);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let mut least = i;
+            for child in 2 * i + 1..(2 * i + 3).min(self.heap.len()) {
+                if self.heap[child].count < self.heap[least].count {
+                    least = child;
+                }
+            }
+            if least == i {
+                break;
+            }
+            self.swap(i, least);
+            i = least;
+        }
+    }
+
+    /// Swaps two counters in the heap and updates their indices.
+    fn swap(&mut self, i: usize, j: usize) {
+        self.heap.swap(i, j);
+        *self.counters.get_mut(&self.heap[i].value).unwrap() = i;
+        *self.counters.get_mut(&self.heap[j].value).unwrap() = j;
+    }
+}
+
+impl<T: Eq + Hash + Clone> Commute for SpaceSaving<T> {
+    fn merge(&mut self, v: SpaceSaving<T>) {
+        if v.is_empty() {
+            return;
+        }
+        if self.is_empty() {
+            *self = v;
+            return;
+        }
+        // A value missing from one summary may have occurred as often as
+        // that summary's smallest count, which is added as error.
+        let (min_a, min_b) = (self.min_count(), v.min_count());
+        let mut merged: HashMap<T, (u64, u64)> = HashMap::new();
+        for c in &self.heap {
+            let (c2, e2) = v.counters.get(&c.value)
+                            .map(|&i| (v.heap[i].count, v.heap[i].error))
+                            .unwrap_or((min_b, min_b));
+            merged.insert(c.value.clone(), (c.count + c2, c.error + e2));
+        }
+        for c in v.heap {
+            if let Entry::Vacant(slot) = merged.entry(c.value) {
+                slot.insert((c.count + min_a, c.error + min_a));
+            }
+        }
+        let capacity = self.capacity.min(v.capacity);
+        let mut counts: Vec<_> = merged.into_iter().collect();
+        counts.sort_by_key(|&(_, (c, _))| Reverse(c));
+        counts.truncate(capacity);
+        self.capacity = capacity;
+        self.total += v.total;
+        self.counters.clear();
+        self.heap.clear();
+        for (value, (count, error)) in counts {
+            self.push(Counter { value, count, error });
+        }
+    }
+}
+
+impl<T: Eq + Hash + Clone> Default for SpaceSaving<T> {
+    fn default() -> SpaceSaving<T> {
+        SpaceSaving::with_capacity(100)
+    }
+}
+
+impl<T: Eq + Hash + Clone> FromIterator<T> for SpaceSaving<T> {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> SpaceSaving<T> {
+        let mut v = SpaceSaving::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: Eq + Hash + Clone> Extend<T> for SpaceSaving<T> {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, Frequencies, merge_all};
+    use testutil::shuffle;
+    use super::SpaceSaving;
+
+    // A long tail in a scrambled order: value `i` occurs `2000 / (i + 1)`
+    // times.
+    fn zipf() -> Vec<u64> {
+        let mut xs: Vec<u64> = (0..2000u64).flat_map(|i| {
+            vec![i; 2000 / (i as usize + 1)]
+        }).collect();
+        shuffle(&mut xs);
+        xs
+    }
+
+    fn check(summary: &SpaceSaving<u64>, data: &[u64]) {
+        let exact: Frequencies<u64> = data.iter().cloned().collect();
+        assert_eq!(summary.total(), data.len() as u64);
+        assert!(summary.len() <= summary.capacity());
+        for v in 0..2000 {
+            let (lower, upper) = summary.bounds(&v);
+            let count = exact.count(&v);
+            assert!(lower <= count && count <= upper,
+                    "{}: {} not in [{}, {}]", v, count, lower, upper);
+        }
+        // Anything more frequent than `total / capacity` is tracked.
+        let threshold = summary.total() / summary.capacity() as u64;
+        for (v, count) in exact.most_frequent() {
+            if count > threshold {
+                assert!(summary.counters.contains_key(v));
+            }
+        }
+        let top: Vec<u64> =
+            summary.top(5).into_iter().map(|(&v, _, _)| v).collect();
+        assert_eq!(top, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn heavy_hitters() {
+        let data = zipf();
+        let mut summary = SpaceSaving::with_capacity(50);
+        summary.extend(data.iter().cloned());
+        check(&summary, &data);
+        let (v, lower, upper) = summary.top(1)[0];
+        assert_eq!(*v, 0);
+        assert!(lower <= 2000 && 2000 <= upper);
+    }
+
+    fn check_heap(summary: &SpaceSaving<u64>) {
+        for (i, c) in summary.heap.iter().enumerate() {
+            assert_eq!(summary.counters[&c.value], i);
+            if i > 0 {
+                assert!(summary.heap[(i - 1) / 2].count <= c.count);
+            }
+        }
+        assert_eq!(summary.counters.len(), summary.heap.len());
+    }
+
+    #[test]
+    fn many_distinct() {
+        // Every sample past the first 1000 evicts the smallest counter.
+        let mut summary = SpaceSaving::with_capacity(1000);
+        summary.extend(0..100_000u64);
+        summary.extend(vec![7; 500]);
+        check_heap(&summary);
+        assert_eq!(summary.len(), 1000);
+        assert_eq!(summary.top(1)[0].0, &7);
+        assert!(summary.bounds(&7).0 >= 500);
+
+        let mut other = SpaceSaving::with_capacity(1000);
+        other.extend(50_000..60_000u64);
+        summary.merge(other);
+        check_heap(&summary);
+    }
+
+    #[test]
+    fn small() {
+        let summary: SpaceSaving<&str> =
+            vec!["a", "b", "a", "c", "a"].into_iter().collect();
+        assert_eq!(summary.most_frequent()[0], (&"a", 3, 3));
+        assert_eq!(summary.bounds(&"z"), (0, 0));
+    }
+
+    #[test]
+    fn merge() {
+        let data = zipf();
+        let shards = data.chunks(1_999).map(|chunk| {
+            let mut summary = SpaceSaving::with_capacity(50);
+            summary.extend(chunk.iter().cloned());
+            summary
+        });
+        let mut merged = merge_all(shards).unwrap();
+        merged.merge(SpaceSaving::new());
+        check(&merged, &data);
+
+        let mut empty = SpaceSaving::new();
+        empty.merge(merged.clone());
+        assert_eq!(empty.capacity(), 50);
+        assert_eq!(empty.total(), merged.total());
+    }
+}
@@ -0,0 +1,293 @@
+use std::cmp::Ordering;
+use std::default::Default;
+use std::f64::consts::PI;
+use std::iter::{FromIterator, IntoIterator};
+
+use num::ToPrimitive;
+
+use Commute;
+
+/// A commutative sketch for approximate quantiles in bounded memory.
+///
+/// This is the merging variant of Ted Dunning's t-digest. Samples are
+/// clustered into centroids whose size is limited by the `k1` scale
+/// function, which keeps centroids near the tails small. This makes
+/// extreme quantiles like `0.999` far more accurate than the median, which
+/// is usually what's wanted from latencies and the like.
+///
+/// The number of centroids is bounded by roughly the `compression`
+/// parameter, regardless of how many samples are added.
+#[derive(Clone, Debug)]
+pub struct TDigest {
+    compression: f64,
+    count: u64,
+    min: f64,
+    max: f64,
+    // Sorted by mean.
+    centroids: Vec<Centroid>,
+    // Samples and centroids that haven't been merged into `centroids` yet.
+    buffer: Vec<Centroid>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+impl TDigest {
+    /// Create an empty digest with the default compression of `100`.
+    pub fn new() -> TDigest {
+        Default::default()
+    }
+
+    /// Create an empty digest with the given compression.
+    ///
+    /// Larger values use more memory and give more accurate quantiles.
+    pub fn with_compression(compression: f64) -> TDigest {
+        assert!(compression >= 1.0,
+                "compression {} must be at least 1", compression);
+        TDigest {
+            compression,
+            count: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            centroids: vec![],
+            buffer: vec![],
+        }
+    }
+
+    /// Add a sample to the digest.
+    ///
+    /// `NaN` samples are ignored.
+    pub fn add<T: ToPrimitive>(&mut self, sample: T) {
+        let sample = sample.to_f64().unwrap();
+        if sample.is_nan() {
+            return;
+        }
+        self.count += 1;
+        self.min = self.min.min(sample);
+        self.max = self.max.max(sample);
+        self.buffer.push(Centroid { mean: sample, weight: 1.0 });
+        if self.buffer.len() as f64 >= 5.0 * self.compression {
+            self.compress();
+        }
+    }
+
+    /// Returns the number of samples in the digest.
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    /// Returns true if there are no samples in the digest.
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Returns the approximate quantile `p` of the data, where `p` is in
+    /// `[0, 1]`.
+    ///
+    /// `None` is returned if and only if there is no data.
+    pub fn quantile(&mut self, p: f64) -> Option<f64> {
+        assert!((0.0..=1.0).contains(&p), "quantile {} is not in [0, 1]", p);
+        if self.count == 0 {
+            return None;
+        }
+        self.compress();
+        let knots = self.knots();
+        let rank = p * (self.count as f64);
+        for w in knots.windows(2) {
+            let ((x1, r1), (x2, r2)) = (w[0], w[1]);
+            if rank <= r2 {
+                if r2 == r1 {
+                    return Some(x2);
+                }
+                return Some(x1 + (rank - r1) / (r2 - r1) * (x2 - x1));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// Returns the approximate fraction of the data that is at most `x`.
+    ///
+    /// Samples equal to `x` count for half. `None` is returned if and only
+    /// if there is no data.
+    pub fn cdf(&mut self, x: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        if x < self.min {
+            return Some(0.0);
+        }
+        if x > self.max {
+            return Some(1.0);
+        }
+        self.compress();
+        let knots = self.knots();
+        let n = self.count as f64;
+        let equal: Vec<f64> =
+            knots.iter().filter(|k| k.0 == x).map(|k| k.1).collect();
+        if !equal.is_empty() {
+            return Some((equal[0] + equal[equal.len() - 1]) / 2.0 / n);
+        }
+        for w in knots.windows(2) {
+            let ((x1, r1), (x2, r2)) = (w[0], w[1]);
+            if x < x2 {
+                return Some((r1 + (x - x1) / (x2 - x1) * (r2 - r1)) / n);
+            }
+        }
+        Some(1.0)
+    }
+
+    /// Returns the `(value, rank)` points that quantiles are interpolated
+    /// between: the minimum, the center of every centroid and the maximum.
+    fn knots(&self) -> Vec<(f64, f64)> {
+        let mut knots = Vec::with_capacity(self.centroids.len() + 2);
+        knots.push((self.min, 0.0));
+        let mut cumulative = 0.0;
+        for c in &self.centroids {
+            knots.push((c.mean, cumulative + c.weight / 2.0));
+            cumulative += c.weight;
+        }
+        knots.push((self.max, cumulative));
+        knots
+    }
+
+    /// Merges all buffered samples and centroids into `centroids`.
+    fn compress(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut all = ::std::mem::take(&mut self.buffer);
+        all.append(&mut self.centroids);
+        all.sort_by(|a, b| {
+            a.mean.partial_cmp(&b.mean).unwrap_or(Ordering::Equal)
+        });
+
+        let total: f64 = all.iter().map(|c| c.weight).sum();
+        let mut merged = Vec::with_capacity(self.compression as usize);
+        let mut so_far = 0.0;
+        let mut limit = total * self.k_inverse(self.k(0.0) + 1.0);
+        let mut cur = all[0];
+        for next in all.into_iter().skip(1) {
+            if so_far + cur.weight + next.weight <= limit {
+                let weight = cur.weight + next.weight;
+                cur.mean += (next.mean - cur.mean) * next.weight / weight;
+                cur.weight = weight;
+            } else {
+                so_far += cur.weight;
+                merged.push(cur);
+                limit = total * self.k_inverse(self.k(so_far / total) + 1.0);
+                cur = next;
+            }
+        }
+        merged.push(cur);
+        self.centroids = merged;
+    }
+
+    /// The `k1` scale function, which maps a quantile to a centroid index.
+    fn k(&self, q: f64) -> f64 {
+        self.compression / (2.0 * PI) * (2.0 * q - 1.0).asin()
+    }
+
+    fn k_inverse(&self, k: f64) -> f64 {
+        if k >= self.compression / 4.0 {
+            return 1.0;
+        }
+        ((2.0 * PI * k / self.compression).sin() + 1.0) / 2.0
+    }
+}
+
+impl Commute for TDigest {
+    fn merge(&mut self, v: TDigest) {
+        self.count += v.count;
+        self.min = self.min.min(v.min);
+        self.max = self.max.max(v.max);
+        self.buffer.extend(v.centroids);
+        self.buffer.extend(v.buffer);
+        self.compress();
+    }
+}
+
+impl Default for TDigest {
+    fn default() -> TDigest {
+        TDigest::with_compression(100.0)
+    }
+}
+
+impl<T: ToPrimitive> FromIterator<T> for TDigest {
+    fn from_iter<I: IntoIterator<Item=T>>(it: I) -> TDigest {
+        let mut v = TDigest::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: ToPrimitive> Extend<T> for TDigest {
+    fn extend<I: IntoIterator<Item=T>>(&mut self, it: I) {
+        for sample in it {
+            self.add(sample);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {Commute, merge_all};
+    use testutil::shuffled;
+    use super::TDigest;
+
+    #[test]
+    fn small() {
+        let mut digest: TDigest = vec![1, 2, 3, 4].into_iter().collect();
+        assert_eq!(digest.quantile(0.0), Some(1.0));
+        assert_eq!(digest.quantile(0.5), Some(2.5));
+        assert_eq!(digest.quantile(1.0), Some(4.0));
+        assert_eq!(digest.cdf(2.0), Some(0.375));
+        assert_eq!(digest.cdf(0.0), Some(0.0));
+        assert_eq!(digest.cdf(5.0), Some(1.0));
+        assert_eq!(TDigest::new().quantile(0.5), None);
+
+        let mut same: TDigest = vec![7; 10].into_iter().collect();
+        assert_eq!(same.quantile(0.3), Some(7.0));
+        assert_eq!(same.cdf(7.0), Some(0.5));
+    }
+
+    #[test]
+    fn accuracy() {
+        let n = 100_000;
+        let mut digest: TDigest = shuffled(n).into_iter().collect();
+        assert!(digest.centroids.len() < 200);
+        assert_eq!(digest.len(), n as usize);
+        for &p in &[0.0001, 0.001, 0.01, 0.25, 0.5, 0.75, 0.99, 0.999] {
+            let expected = p * (n - 1) as f64;
+            let got = digest.quantile(p).unwrap();
+            let tail = !(0.01..=0.99).contains(&p);
+            let tolerance = if tail { 0.0005 } else { 0.01 };
+            assert!((got - expected).abs() / (n as f64) < tolerance,
+                    "p = {}: expected {}, got {}", p, expected, got);
+            let cdf = digest.cdf(expected).unwrap();
+            assert!((cdf - p).abs() < tolerance,
+                    "cdf({}): expected {}, got {}", expected, p, cdf);
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let n = 100_000;
+        let xs = shuffled(n);
+        let digests = xs.chunks(7_919).map(|c| c.iter().cloned().collect());
+        let mut merged: TDigest = merge_all(digests).unwrap();
+        merged.merge(TDigest::new());
+        assert_eq!(merged.len(), n as usize);
+        assert_eq!(merged.quantile(0.0), Some(0.0));
+        assert_eq!(merged.quantile(1.0), Some((n - 1) as f64));
+        for &p in &[0.001, 0.5, 0.999] {
+            let expected = p * (n - 1) as f64;
+            let got = merged.quantile(p).unwrap();
+            let tolerance = if p == 0.5 { 0.01 } else { 0.001 };
+            assert!((got - expected).abs() / (n as f64) < tolerance,
+                    "p = {}: expected {}, got {}", p, expected, got);
+        }
+    }
+}
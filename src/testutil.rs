@@ -0,0 +1,41 @@
+/// A xorshift generator, so test data is the same on every run.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new() -> XorShift {
+        XorShift(0x2545f4914f6cdd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % n
+    }
+}
+
+/// Returns true if `a` and `b` are equal up to rounding error.
+pub fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-12
+}
+
+/// Shuffles `xs` into the same scrambled order on every run.
+pub fn shuffle<T>(xs: &mut [T]) {
+    let mut rng = XorShift::new();
+    for i in (1..xs.len()).rev() {
+        xs.swap(i, rng.below(i as u64 + 1) as usize);
+    }
+}
+
+/// Returns `0..len` in a scrambled order.
+pub fn shuffled(len: u64) -> Vec<u64> {
+    let mut xs: Vec<u64> = (0..len).collect();
+    shuffle(&mut xs);
+    xs
+}
+
+/// Returns `len` integers in `[0, below)` that are the same on every run.
+pub fn pseudo_random(len: usize, below: u64) -> Vec<u64> {
+    let mut rng = XorShift::new();
+    (0..len).map(|_| rng.below(below)).collect()
+}
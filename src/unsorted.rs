@@ -1,74 +1,216 @@
 use std::default::Default;
-use std::iter::{FromIterator, IntoIterator};
+use std::iter::{FromIterator, IntoIterator, Peekable};
 use num::ToPrimitive;
 
-use {Commute, Partial};
+use {Commute, ModeResult, Partial, extreme_counts};
 
 /// Compute the exact median on a stream of data.
 ///
-/// (This has time complexity `O(nlogn)` and space complexity `O(n)`.)
+/// (This has expected time complexity `O(n)` and space complexity `O(n)`.)
 pub fn median<I>(it: I) -> Option<f64>
         where I: Iterator, <I as Iterator>::Item: PartialOrd + ToPrimitive {
     it.collect::<Unsorted<_>>().median()
 }
 
+/// Compute the exact quantile `p` on a stream of data.
+///
+/// `p` must be in the range `[0, 1]`. Values between two data points are
+/// linearly interpolated (see `Interpolation::Linear`).
+///
+/// (This has expected time complexity `O(n)` and space complexity `O(n)`.)
+///
+/// # Panics
+///
+/// If the data is not empty and `p` is NaN or not in `[0, 1]`.
+pub fn quantile<I>(it: I, p: f64) -> Option<f64>
+        where I: Iterator, <I as Iterator>::Item: PartialOrd + ToPrimitive {
+    it.collect::<Unsorted<_>>().quantile(p)
+}
+
 /// Compute the exact mode on a stream of data.
 ///
 /// (This has time complexity `O(nlogn)` and space complexity `O(n)`.)
 ///
-/// If the data does not have a mode, then `None` is returned.
+/// If the data does not have a mode, then `None` is returned. Use `modes`
+/// to tell empty data apart from data with several modes.
 pub fn mode<T, I>(it: I) -> Option<T>
        where T: PartialOrd + Clone, I: Iterator<Item=T> {
     it.collect::<Unsorted<T>>().mode()
 }
 
-fn median_on_sorted<T>(data: &[T]) -> Option<f64>
-        where T: PartialOrd + ToPrimitive {
-    Some(match data.len() {
-        0 => return None,
-        1 => data[0].to_f64().unwrap(),
-        len if len % 2 == 0 => {
-            let v1 = data[(len / 2) - 1].to_f64().unwrap();
-            let v2 = data[len / 2].to_f64().unwrap();
-            (v1 + v2) / 2.0
-        }
-        len => {
-            data[len / 2].to_f64().unwrap()
-        }
-    })
-}
-
-fn mode_on_sorted<T, I>(it: I) -> Option<T>
-        where T: PartialOrd, I: Iterator<Item=T> {
-    // This approach to computing the mode works very nicely when the
-    // number of samples is large and is close to its cardinality.
-    // In other cases, a hashmap would be much better.
-    // But really, how can we know this when given an arbitrary stream?
-    // Might just switch to a hashmap to track frequencies. That would also
-    // be generally useful for discovering the cardinality of a sample.
-    let (mut mode, mut next) = (None, None);
-    let (mut mode_count, mut next_count) = (0usize, 0usize);
-    for x in it {
-        if mode.as_ref().map(|y| y == &x).unwrap_or(false) {
-            mode_count += 1;
-        } else if next.as_ref().map(|y| y == &x).unwrap_or(false) {
-            next_count += 1;
-        } else {
-            next = Some(x);
-            next_count = 0;
+/// Compute every mode on a stream of data.
+///
+/// (This has time complexity `O(nlogn)` and space complexity `O(n)`.)
+pub fn modes<T, I>(it: I) -> ModeResult<T>
+       where T: PartialOrd + Clone, I: Iterator<Item=T> {
+    it.collect::<Unsorted<T>>().modes()
+}
+
+/// Compute every antimode, or least frequent value, on a stream of data.
+///
+/// (This has time complexity `O(nlogn)` and space complexity `O(n)`.)
+pub fn antimodes<T, I>(it: I) -> ModeResult<T>
+       where T: PartialOrd + Clone, I: Iterator<Item=T> {
+    it.collect::<Unsorted<T>>().antimodes()
+}
+
+/// The method used to pick a quantile that falls between two data points.
+///
+/// `Type1` through `Type9` are the nine sample quantile definitions from
+/// Hyndman and Fan (1996), which are also the `type` argument of R's
+/// `quantile` function. `Linear`, `Lower`, `Higher`, `Nearest` and `Midpoint`
+/// correspond to the interpolation methods of NumPy's `quantile` function.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Linear interpolation between the closest ranks. This is the default
+    /// in both R and NumPy and is identical to `Type7`.
+    #[default]
+    Linear,
+    /// The lower of the two closest data points.
+    Lower,
+    /// The higher of the two closest data points.
+    Higher,
+    /// The closest data point. Ties go to the data point with an even index.
+    Nearest,
+    /// The average of the two closest data points.
+    Midpoint,
+    /// Inverse of the empirical distribution function.
+    Type1,
+    /// Like `Type1`, but averages at discontinuities.
+    Type2,
+    /// The nearest even order statistic (SAS definition 2).
+    Type3,
+    /// Linear interpolation of the empirical distribution function.
+    Type4,
+    /// Piecewise linear function with knots at midway of the data points.
+    Type5,
+    /// Linear interpolation of the expectations of the order statistics.
+    /// This is used by Minitab and SPSS.
+    Type6,
+    /// Linear interpolation of the modes of the order statistics.
+    Type7,
+    /// Approximately median-unbiased regardless of the distribution.
+    Type8,
+    /// Approximately unbiased if the data is normally distributed.
+    Type9,
+}
+
+/// Returns the two (0-based) data indices that quantile `p` lies between,
+/// along with the weight given to the second one.
+fn quantile_index(len: usize, p: f64, interp: Interpolation)
+                 -> (usize, usize, f64) {
+    use self::Interpolation::*;
+
+    assert!((0.0..=1.0).contains(&p), "quantile {} is not in [0, 1]", p);
+    let n = len as f64;
+    // Positions are 1-based in Hyndman and Fan, which we follow here and
+    // clamp to the data at the end.
+    let h = match interp {
+        Type1 | Type2 | Type3 | Type4 => n * p,
+        Type5 => n * p + 0.5,
+        Type6 => (n + 1.0) * p,
+        Linear | Lower | Higher | Nearest | Midpoint | Type7 => {
+            (n - 1.0) * p + 1.0
+        }
+        Type8 => (n + 1.0 / 3.0) * p + 1.0 / 3.0,
+        Type9 => (n + 0.25) * p + 0.375,
+    };
+    // The discontinuous definitions need some slack to avoid jumping to
+    // the next data point on rounding error, like R does.
+    let fuzz = 4.0 * f64::EPSILON * h.max(1.0);
+    let (lo, hi, g) = match interp {
+        Type1 => {
+            let j = (h - fuzz).ceil();
+            (j, j, 0.0)
         }
+        Type2 => {
+            let j = (h + fuzz).floor();
+            if h - j < fuzz {
+                (j, j + 1.0, 0.5)
+            } else {
+                (j + 1.0, j + 1.0, 0.0)
+            }
+        }
+        Type3 => {
+            let j = round_half_even(h, fuzz);
+            (j, j, 0.0)
+        }
+        Nearest => {
+            let j = round_half_even(h - 1.0, fuzz) + 1.0;
+            (j, j, 0.0)
+        }
+        Lower => (h.floor(), h.floor(), 0.0),
+        Higher => (h.ceil(), h.ceil(), 0.0),
+        Midpoint => (h.floor(), h.ceil(), 0.5),
+        _ => (h.floor(), h.floor() + 1.0, h - h.floor()),
+    };
+    let clamp = |i: f64| (i.max(1.0).min(n) as usize) - 1;
+    (clamp(lo), clamp(hi), g)
+}
+
+fn round_half_even(x: f64, fuzz: f64) -> f64 {
+    let j = x.floor();
+    if (x - j - 0.5).abs() >= fuzz {
+        x.round()
+    } else if j % 2.0 == 0.0 {
+        j
+    } else {
+        j + 1.0
+    }
+}
+
+fn interpolate<T>(data: &[T], (lo, hi, g): (usize, usize, f64)) -> f64
+        where T: ToPrimitive {
+    let v1 = data[lo].to_f64().unwrap();
+    if g == 0.0 {
+        return v1;
+    }
+    let v2 = data[hi].to_f64().unwrap();
+    v1 + g * (v2 - v1)
+}
 
-        if next_count > mode_count {
-            mode = next;
-            mode_count = next_count;
-            next = None;
-            next_count = 0;
-        } else if next_count == mode_count {
-            mode = None;
-            mode_count = 0usize;
+/// Partially sorts `data` so that every index in `positions` holds the
+/// element it would hold if `data` were fully sorted.
+///
+/// `positions` must be sorted and deduplicated. They are relative to the
+/// start of the original slice, of which `data` starts at `offset`.
+fn select_many<T: Ord>(data: &mut [T], positions: &[usize], offset: usize) {
+    if positions.is_empty() {
+        return;
+    }
+    // Selecting the middle position first splits the remaining work in
+    // half, so `k` positions cost `O(n log k)` instead of `O(nk)`.
+    let mid = positions.len() / 2;
+    let k = positions[mid] - offset;
+    data.select_nth_unstable(k);
+    let (left, right) = data.split_at_mut(k);
+    select_many(left, &positions[..mid], offset);
+    select_many(&mut right[1..], &positions[mid + 1..], offset + k + 1);
+}
+
+/// Counts each run of equal values in sorted data.
+struct Runs<I: Iterator> {
+    it: Peekable<I>,
+}
+
+impl<I: Iterator> Runs<I> {
+    fn new(it: I) -> Runs<I> {
+        Runs { it: it.peekable() }
+    }
+}
+
+impl<I> Iterator for Runs<I> where I: Iterator, I::Item: PartialEq {
+    type Item = (I::Item, u64);
+
+    fn next(&mut self) -> Option<(I::Item, u64)> {
+        let x = self.it.next()?;
+        let mut n = 1;
+        while self.it.peek() == Some(&x) {
+            self.it.next();
+            n += 1;
         }
+        Some((x, n))
     }
-    mode
 }
 
 /// A commutative data structure for lazily sorted sequences of data.
@@ -82,6 +224,8 @@ fn mode_on_sorted<T, I>(it: I) -> Option<T>
 pub struct Unsorted<T> {
     data: Vec<Partial<T>>,
     sorted: bool,
+    /// The positions holding their sorted element after the last selection.
+    selected: Vec<usize>,
 }
 
 impl<T: PartialOrd> Unsorted<T> {
@@ -101,14 +245,53 @@ impl<T: PartialOrd> Unsorted<T> {
         self.data.len()
     }
 
+    /// Returns true if there are no data points.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
     fn sort(&mut self) {
         if !self.sorted {
             self.data.sort();
+            self.sorted = true;
+            self.selected.clear();
+        }
+    }
+
+    /// Makes sure each index in `positions` holds the element it would
+    /// hold if the data were sorted.
+    ///
+    /// The first time only a few positions are needed, they are selected.
+    /// Asking again for positions that were already selected does nothing,
+    /// while asking for any others sorts the data, as does asking for many
+    /// positions at once.
+    fn select(&mut self, positions: &mut Vec<usize>) {
+        if self.sorted {
+            return;
+        }
+        positions.sort();
+        positions.dedup();
+        let selected = &self.selected;
+        if positions.iter().all(|i| selected.binary_search(i).is_ok()) {
+            return;
+        }
+        if !self.selected.is_empty()
+                || positions.len() as f64 > (self.data.len() as f64).log2() {
+            self.sort();
+        } else {
+            select_many(&mut self.data, positions, 0);
+            self.selected.clone_from(positions);
         }
     }
 
     fn dirtied(&mut self) {
         self.sorted = false;
+        self.selected.clear();
+    }
+
+    /// Returns the data in no particular order.
+    pub(crate) fn as_slice(&self) -> &[Partial<T>] {
+        &self.data
     }
 }
 
@@ -123,24 +306,126 @@ impl<T: PartialOrd + Eq + Clone> Unsorted<T> {
 
 impl<T: PartialOrd + Clone> Unsorted<T> {
     /// Returns the mode of the data.
+    ///
+    /// This is `None` if there is no data or if several values are tied.
     pub fn mode(&mut self) -> Option<T> {
+        self.extremes(true).unique().map(|v| v.0.clone())
+    }
+
+    /// Returns every value that occurs most often, in ascending order, and
+    /// how many times each occurs.
+    pub fn modes(&mut self) -> ModeResult<T> {
+        self.extremes(true).map(|v| v.0.clone())
+    }
+
+    /// Returns every value that occurs least often, in ascending order, and
+    /// how many times each occurs.
+    pub fn antimodes(&mut self) -> ModeResult<T> {
+        self.extremes(false).map(|v| v.0.clone())
+    }
+
+    fn extremes(&mut self, most: bool) -> ModeResult<&Partial<T>> {
         self.sort();
-        mode_on_sorted(self.data.iter()).map(|p| p.0.clone())
+        extreme_counts(Runs::new(self.data.iter()), most)
     }
 }
 
 impl<T: PartialOrd + ToPrimitive> Unsorted<T> {
     /// Returns the median of the data.
     pub fn median(&mut self) -> Option<f64> {
-        self.sort();
-        median_on_sorted(&*self.data)
+        self.quantile(0.5)
+    }
+
+    /// Returns the quantile `p` of the data, where `p` is in `[0, 1]`.
+    ///
+    /// Values between two data points are linearly interpolated. Use
+    /// `quantile_with` to pick a different method.
+    ///
+    /// # Panics
+    ///
+    /// If the data is not empty and `p` is NaN or not in `[0, 1]`.
+    pub fn quantile(&mut self, p: f64) -> Option<f64> {
+        self.quantile_with(p, Interpolation::Linear)
+    }
+
+    /// Returns the quantile `p` of the data using the given interpolation
+    /// method.
+    ///
+    /// # Panics
+    ///
+    /// If the data is not empty and `p` is NaN or not in `[0, 1]`.
+    pub fn quantile_with(&mut self, p: f64, interp: Interpolation)
+                        -> Option<f64> {
+        self.quantiles_with(&[p], interp).map(|qs| qs[0])
+    }
+
+    /// Returns each of the quantiles in `ps` of the data.
+    ///
+    /// `None` is returned if and only if there is no data.
+    ///
+    /// # Panics
+    ///
+    /// If the data is not empty and any `p` in `ps` is NaN or not in `[0, 1]`.
+    pub fn quantiles(&mut self, ps: &[f64]) -> Option<Vec<f64>> {
+        self.quantiles_with(ps, Interpolation::Linear)
+    }
+
+    /// Returns each of the quantiles in `ps` of the data using the given
+    /// interpolation method.
+    ///
+    /// The first time a handful of quantiles is requested, only the data
+    /// points needed are selected, which takes expected linear time. Asking
+    /// for the same quantiles again is then free until the data changes.
+    /// Otherwise, the data is sorted once, which makes subsequent calls
+    /// cheap.
+    ///
+    /// # Panics
+    ///
+    /// If the data is not empty and any `p` in `ps` is NaN or not in `[0, 1]`.
+    pub fn quantiles_with(&mut self, ps: &[f64], interp: Interpolation)
+                         -> Option<Vec<f64>> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let len = self.data.len();
+        let idxs: Vec<_> =
+            ps.iter().map(|&p| quantile_index(len, p, interp)).collect();
+        let mut positions = Vec::with_capacity(2 * idxs.len());
+        for &(lo, hi, _) in &idxs {
+            positions.push(lo);
+            positions.push(hi);
+        }
+        self.select(&mut positions);
+        let data = &self.data;
+        Some(idxs.into_iter().map(|idx| interpolate(data, idx)).collect())
+    }
+
+    /// Returns the percentile `pct` of the data, where `pct` is in
+    /// `[0, 100]`.
+    ///
+    /// # Panics
+    ///
+    /// If the data is not empty and `pct` is NaN or not in `[0, 100]`.
+    pub fn percentile(&mut self, pct: f64) -> Option<f64> {
+        self.quantile(pct / 100.0)
+    }
+
+    /// Returns each of the percentiles in `pcts` of the data.
+    ///
+    /// # Panics
+    ///
+    /// If the data is not empty and any `pct` in `pcts` is NaN or not in
+    /// `[0, 100]`.
+    pub fn percentiles(&mut self, pcts: &[f64]) -> Option<Vec<f64>> {
+        let ps: Vec<f64> = pcts.iter().map(|&pct| pct / 100.0).collect();
+        self.quantiles(&ps)
     }
 }
 
 impl<T: PartialOrd> Commute for Unsorted<T> {
     fn merge(&mut self, v: Unsorted<T>) {
         self.dirtied();
-        self.data.extend(v.data.into_iter());
+        self.data.extend(v.data);
     }
 }
 
@@ -149,6 +434,7 @@ impl<T: PartialOrd> Default for Unsorted<T> {
         Unsorted {
             data: Vec::with_capacity(1000),
             sorted: true,
+            selected: Vec::new(),
         }
     }
 }
@@ -168,9 +454,165 @@ impl<T: PartialOrd> Extend<T> for Unsorted<T> {
     }
 }
 
+/// A commutative data structure for lazily sorted sequences of weighted
+/// data.
+///
+/// This is like `Unsorted`, except every data point has a non-negative
+/// weight that scales its contribution to the median and quantiles.
+#[derive(Clone)]
+pub struct WeightedUnsorted<T> {
+    data: Vec<(Partial<T>, f64)>,
+    weight: f64,
+    sorted: bool,
+}
+
+impl<T: PartialOrd> WeightedUnsorted<T> {
+    /// Create initial empty state.
+    pub fn new() -> WeightedUnsorted<T> {
+        Default::default()
+    }
+
+    /// Add a new element with the given weight to the set.
+    ///
+    /// The weight must be non-negative. Elements with a weight of `0` are
+    /// ignored.
+    ///
+    /// # Panics
+    ///
+    /// If `weight` is negative, infinite or NaN.
+    pub fn add(&mut self, v: T, weight: f64) {
+        assert!(weight.is_finite() && weight >= 0.0,
+                "sample weight {} is negative or not finite", weight);
+        if weight > 0.0 {
+            self.sorted = false;
+            self.weight += weight;
+            self.data.push((Partial(v), weight));
+        }
+    }
+
+    /// Return the number of data points.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if there are no data points.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the sum of the weights of all data points.
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    fn sort(&mut self) {
+        if !self.sorted {
+            self.data.sort_by(|a, b| a.0.cmp(&b.0));
+            self.sorted = true;
+        }
+    }
+}
+
+impl<T: PartialOrd + ToPrimitive> WeightedUnsorted<T> {
+    /// Returns the weighted median of the data.
+    pub fn median(&mut self) -> Option<f64> {
+        self.quantile(0.5)
+    }
+
+    /// Returns the weighted quantile `p` of the data, where `p` is in
+    /// `[0, 1]`.
+    ///
+    /// This is the smallest data point at which the cumulative weight
+    /// reaches `p` times the total weight. When the cumulative weight is
+    /// exactly that, the data point is averaged with the next one. With all
+    /// weights equal to `1`, this is `Interpolation::Type2`, and the median
+    /// is the same as `Unsorted::median`.
+    ///
+    /// # Panics
+    ///
+    /// If the data is not empty and `p` is NaN or not in `[0, 1]`.
+    pub fn quantile(&mut self, p: f64) -> Option<f64> {
+        self.quantiles(&[p]).map(|qs| qs[0])
+    }
+
+    /// Returns each of the weighted quantiles in `ps` of the data.
+    ///
+    /// # Panics
+    ///
+    /// If the data is not empty and any `p` in `ps` is NaN or not in `[0, 1]`.
+    pub fn quantiles(&mut self, ps: &[f64]) -> Option<Vec<f64>> {
+        if self.data.is_empty() {
+            return None;
+        }
+        self.sort();
+        Some(ps.iter().map(|&p| self.quantile_on_sorted(p)).collect())
+    }
+
+    fn quantile_on_sorted(&self, p: f64) -> f64 {
+        assert!((0.0..=1.0).contains(&p), "quantile {} is not in [0, 1]", p);
+        let target = p * self.weight;
+        let fuzz = 4.0 * f64::EPSILON * self.weight;
+        let mut cumulative = 0.0;
+        for (i, &(ref v, w)) in self.data.iter().enumerate() {
+            cumulative += w;
+            if cumulative + fuzz < target {
+                continue;
+            }
+            let v = v.to_f64().unwrap();
+            return match self.data.get(i + 1) {
+                Some(next) if (cumulative - target).abs() <= fuzz => {
+                    (v + next.0.to_f64().unwrap()) / 2.0
+                }
+                _ => v,
+            };
+        }
+        self.data[self.data.len() - 1].0.to_f64().unwrap()
+    }
+}
+
+impl<T: PartialOrd> Commute for WeightedUnsorted<T> {
+    fn merge(&mut self, v: WeightedUnsorted<T>) {
+        self.sorted = false;
+        self.weight += v.weight;
+        self.data.extend(v.data);
+    }
+}
+
+impl<T: PartialOrd> Default for WeightedUnsorted<T> {
+    fn default() -> WeightedUnsorted<T> {
+        WeightedUnsorted {
+            data: Vec::with_capacity(1000),
+            weight: 0.0,
+            sorted: true,
+        }
+    }
+}
+
+impl<T: PartialOrd> FromIterator<(T, f64)> for WeightedUnsorted<T> {
+    fn from_iter<I: IntoIterator<Item=(T, f64)>>(it: I)
+                 -> WeightedUnsorted<T> {
+        let mut v = WeightedUnsorted::new();
+        v.extend(it);
+        v
+    }
+}
+
+impl<T: PartialOrd> Extend<(T, f64)> for WeightedUnsorted<T> {
+    fn extend<I: IntoIterator<Item=(T, f64)>>(&mut self, it: I) {
+        for (v, weight) in it {
+            self.add(v, weight);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::{median, mode};
+    use {Commute, ModeResult};
+    use testutil::pseudo_random;
+    use super::{
+        Interpolation, Unsorted, WeightedUnsorted, antimodes, median, mode,
+        modes, quantile,
+    };
 
     #[test]
     fn median_stream() {
@@ -187,6 +629,36 @@ mod test {
         assert_eq!(mode(vec![1usize, 1, 2, 3, 3].into_iter()), None);
     }
 
+    #[test]
+    fn mode_tied_after_longer_run() {
+        // 1 and 2 are tied, so the shorter run of 3 must not win.
+        let data = vec![1usize, 1, 1, 2, 2, 2, 3, 3];
+        assert_eq!(mode(data.into_iter()), None);
+    }
+
+    #[test]
+    fn modes_stream() {
+        assert_eq!(modes(Vec::<usize>::new().into_iter()), ModeResult::Empty);
+        assert_eq!(modes(vec![4usize, 3, 3, 3].into_iter()),
+                   ModeResult::Unique(3, 3));
+        assert_eq!(modes(vec![3usize, 1, 2, 3, 1].into_iter()),
+                   ModeResult::Multiple(vec![1, 3], 2));
+        assert_eq!(modes(vec![2.5f64, 1.0].into_iter()),
+                   ModeResult::Multiple(vec![1.0, 2.5], 1));
+    }
+
+    #[test]
+    fn antimodes_stream() {
+        assert_eq!(antimodes(Vec::<usize>::new().into_iter()),
+                   ModeResult::Empty);
+        assert_eq!(antimodes(vec![4usize, 3, 3, 3].into_iter()),
+                   ModeResult::Unique(4, 1));
+        assert_eq!(antimodes(vec![3usize, 1, 2, 3, 4, 1].into_iter()),
+                   ModeResult::Multiple(vec![2, 4], 1));
+        assert_eq!(antimodes(vec![5usize; 3].into_iter()),
+                   ModeResult::Unique(5, 3));
+    }
+
     #[test]
     fn median_floats() {
         assert_eq!(median(vec![3.0f64, 5.0, 7.0, 9.0].into_iter()), Some(6.0));
@@ -202,4 +674,163 @@ mod test {
         assert_eq!(mode(vec![4.0f64, 3.0, 3.0, 3.0].into_iter()), Some(3.0));
         assert_eq!(mode(vec![1.0f64, 1.0, 2.0, 3.0, 3.0].into_iter()), None);
     }
+
+    #[test]
+    fn quantile_stream() {
+        assert_eq!(quantile(vec![3usize, 5, 7, 9].into_iter(), 0.5),
+                   Some(6.0));
+        assert_eq!(quantile(vec![3usize, 5, 7, 9].into_iter(), 0.0),
+                   Some(3.0));
+        assert_eq!(quantile(vec![3usize, 5, 7, 9].into_iter(), 1.0),
+                   Some(9.0));
+        assert_eq!(quantile(Vec::<usize>::new().into_iter(), 0.5), None);
+    }
+
+    #[test]
+    fn quantile_hyndman_fan() {
+        use super::Interpolation::*;
+
+        // Expected values are from R's `quantile(1:10, 0.1, type = t)`.
+        let mut data: Unsorted<usize> = (1..11).rev().collect();
+        let expected = [
+            (Type1, 1.0), (Type2, 1.5), (Type3, 1.0), (Type4, 1.0),
+            (Type5, 1.5), (Type6, 1.1), (Type7, 1.9),
+            (Type8, 1.0 + 11.0 / 30.0), (Type9, 1.4),
+        ];
+        for &(interp, want) in &expected {
+            let got = data.quantile_with(0.1, interp).unwrap();
+            assert!((got - want).abs() < 1e-12,
+                    "{:?}: expected {}, got {}", interp, want, got);
+        }
+    }
+
+    #[test]
+    fn quantile_numpy() {
+        use super::Interpolation::*;
+
+        // Expected values are from `numpy.quantile(range(1, 11), 0.25)`.
+        let mut data: Unsorted<f64> =
+            vec![4.0, 1.0, 3.0, 2.0, 10.0, 5.0, 9.0, 6.0, 8.0, 7.0]
+            .into_iter().collect();
+        assert_eq!(data.quantile_with(0.25, Linear), Some(3.25));
+        assert_eq!(data.quantile_with(0.25, Lower), Some(3.0));
+        assert_eq!(data.quantile_with(0.25, Higher), Some(4.0));
+        assert_eq!(data.quantile_with(0.25, Nearest), Some(3.0));
+        assert_eq!(data.quantile_with(0.25, Midpoint), Some(3.5));
+
+        // Ties in `Nearest` go to the even index.
+        let mut data: Unsorted<usize> = vec![1, 2, 3, 4].into_iter().collect();
+        assert_eq!(data.quantile_with(0.5, Nearest), Some(3.0));
+        let mut data: Unsorted<usize> = vec![1, 2, 3].into_iter().collect();
+        assert_eq!(data.quantile_with(0.25, Nearest), Some(1.0));
+    }
+
+    #[test]
+    fn percentiles() {
+        let mut data: Unsorted<usize> = (1..101).collect();
+        assert_eq!(data.percentiles(&[0.0, 25.0, 50.0, 100.0]),
+                   Some(vec![1.0, 25.75, 50.5, 100.0]));
+        assert_eq!(data.percentile(99.0), data.quantile(0.99));
+        assert_eq!(Unsorted::<usize>::new().percentiles(&[50.0]), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn quantile_nan() {
+        let mut data: Unsorted<usize> = (1..11).collect();
+        data.quantile(f64::NAN);
+    }
+
+    #[test]
+    fn median_matches_quantile() {
+        let mut data: Unsorted<f64> =
+            vec![1.0, 2.5, 3.0, 7.5].into_iter().collect();
+        assert_eq!(data.median(), data.quantile(0.5));
+        assert_eq!(data.quantile_with(0.5, Interpolation::default()),
+                   Some(2.75));
+    }
+
+    #[test]
+    fn selection_matches_sort() {
+        let data = pseudo_random(10_001, 1000);
+        let mut sorted = data.clone();
+        sorted.sort();
+        let ps = [0.0, 0.01, 0.25, 0.5, 0.9, 0.999, 1.0];
+        let expected: Vec<f64> = sorted.iter().cloned()
+                                       .collect::<Unsorted<_>>()
+                                       .quantiles(&ps).unwrap();
+
+        // A few quantiles are selected without sorting the data.
+        let mut few: Unsorted<u64> = data.iter().cloned().collect();
+        assert_eq!(few.quantiles(&ps[..3]), Some(expected[..3].to_vec()));
+        assert!(!few.sorted);
+
+        // Asking for them again reuses the selection, while asking for
+        // another quantile sorts the data.
+        assert_eq!(few.quantile(ps[1]), Some(expected[1]));
+        assert!(!few.sorted);
+        assert_eq!(few.median(), Some(expected[3]));
+        assert!(few.sorted);
+
+        // Adding data throws the selection away.
+        few.add(1000);
+        assert!(!few.sorted);
+        assert!(few.selected.is_empty());
+
+        // Many quantiles fall back to sorting, after which it sticks.
+        let many_ps: Vec<f64> = (0..101).map(|i| i as f64 / 100.0).collect();
+        let mut many: Unsorted<u64> = data.iter().cloned().collect();
+        many.quantiles(&many_ps).unwrap();
+        assert!(many.sorted);
+        assert_eq!(many.quantiles(&ps), Some(expected));
+    }
+
+    #[test]
+    fn weighted_median() {
+        let mut data: WeightedUnsorted<usize> =
+            vec![(3, 1.0), (1, 1.0), (2, 1.0), (4, 1.0)].into_iter().collect();
+        assert_eq!(data.median(), Some(2.5));
+
+        let mut data: WeightedUnsorted<f64> =
+            vec![(1.0, 0.1), (2.0, 0.2), (3.0, 0.3), (4.0, 0.4)]
+            .into_iter().collect();
+        assert_eq!(data.median(), Some(3.0));
+        assert_eq!(data.quantiles(&[0.0, 0.3, 0.9, 1.0]),
+                   Some(vec![1.0, 2.5, 4.0, 4.0]));
+        assert_eq!(WeightedUnsorted::<f64>::new().median(), None);
+
+        let mut zero = WeightedUnsorted::new();
+        zero.add(1.0, 0.0);
+        assert_eq!(zero.len(), 0);
+        assert_eq!(zero.median(), None);
+    }
+
+    #[test]
+    fn weighted_matches_unweighted() {
+        let xs = pseudo_random(1001, 1000);
+        let mut unweighted: Unsorted<u64> = xs.iter().cloned().collect();
+        let mut weighted: WeightedUnsorted<u64> =
+            xs.iter().map(|&x| (x, 1.0)).collect();
+        assert_eq!(weighted.median(), unweighted.median());
+        assert_eq!(weighted.quantile(0.1),
+                   unweighted.quantile_with(0.1, Interpolation::Type2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn weighted_nan() {
+        WeightedUnsorted::new().add(1usize, f64::NAN);
+    }
+
+    #[test]
+    fn weighted_merge() {
+        let mut v1: WeightedUnsorted<usize> =
+            vec![(1, 5.0), (2, 0.0)].into_iter().collect();
+        let v2: WeightedUnsorted<usize> =
+            vec![(10, 2.0), (20, 3.0)].into_iter().collect();
+        v1.merge(v2);
+        assert_eq!(v1.len(), 3);
+        assert_eq!(v1.weight(), 10.0);
+        assert_eq!(v1.median(), Some(5.5));
+    }
 }
@@ -0,0 +1,432 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::default::Default;
+
+use num::ToPrimitive;
+
+use {OnlineStats, Partial};
+
+/// Defines an interface for statistics that samples can be evicted from.
+///
+/// Samples are always evicted in the order they were inserted, which is
+/// what makes it possible to implement this for statistics like the
+/// minimum that can't forget an arbitrary sample.
+pub trait Evict<T> {
+    /// Adds a new sample.
+    fn insert(&mut self, sample: T);
+
+    /// Removes the oldest sample still present, which is equal to `sample`.
+    fn evict(&mut self, sample: &T);
+}
+
+impl<T: ToPrimitive> Evict<T> for OnlineStats {
+    fn insert(&mut self, sample: T) {
+        self.add(sample);
+    }
+
+    fn evict(&mut self, sample: &T) {
+        self.remove(sample.to_f64().unwrap());
+    }
+}
+
+/// Statistics over the last `capacity` samples of a stream.
+///
+/// Once the window is full, adding a sample evicts the oldest one from the
+/// underlying statistic `S`.
+#[derive(Clone, Debug)]
+pub struct Window<S, T> {
+    stats: S,
+    samples: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<S: Evict<T>, T: Clone> Window<S, T> {
+    /// Create an empty window over at most `capacity` samples.
+    pub fn new(capacity: usize, stats: S) -> Window<S, T> {
+        assert!(capacity > 0, "window capacity must be positive");
+        Window {
+            stats,
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Add a new sample, evicting the oldest one if the window is full.
+    pub fn add(&mut self, sample: T) {
+        if self.samples.len() == self.capacity {
+            let old = self.samples.pop_front().unwrap();
+            self.stats.evict(&old);
+        }
+        self.stats.insert(sample.clone());
+        self.samples.push_back(sample);
+    }
+
+    /// Returns the statistics over the samples in the window.
+    pub fn stats(&self) -> &S {
+        &self.stats
+    }
+
+    /// Returns the maximum number of samples in the window.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of samples in the window.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns true if there are no samples in the window.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+/// Statistics over the samples of a stream seen in the last `duration`
+/// units of time.
+///
+/// The window covers the half-open interval `(now - duration, now]`, where
+/// `now` is the latest timestamp given to `add` or `expire`.
+#[derive(Clone, Debug)]
+pub struct TimeWindow<S, T> {
+    stats: S,
+    samples: VecDeque<(f64, T)>,
+    duration: f64,
+    now: f64,
+}
+
+impl<S: Evict<T>, T: Clone> TimeWindow<S, T> {
+    /// Create an empty window over the last `duration` units of time.
+    pub fn new(duration: f64, stats: S) -> TimeWindow<S, T> {
+        assert!(duration > 0.0, "window duration {} is not positive",
+                duration);
+        TimeWindow {
+            stats,
+            samples: VecDeque::new(),
+            duration,
+            now: f64::NEG_INFINITY,
+        }
+    }
+
+    /// Add a new sample observed at `time`, evicting samples that are now
+    /// too old.
+    ///
+    /// # Panics
+    ///
+    /// If `time` is NaN or earlier than the current time.
+    pub fn add(&mut self, sample: T, time: f64) {
+        self.expire(time);
+        self.stats.insert(sample.clone());
+        self.samples.push_back((time, sample));
+    }
+
+    /// Advance the current time to `now`, evicting samples that are now
+    /// too old.
+    ///
+    /// # Panics
+    ///
+    /// If `now` is NaN or earlier than the current time.
+    pub fn expire(&mut self, now: f64) {
+        assert!(now >= self.now,
+                "time went backwards from {} to {}", self.now, now);
+        self.now = now;
+        while self.samples.front()
+                          .map(|&(t, _)| t <= now - self.duration)
+                          .unwrap_or(false) {
+            let (_, old) = self.samples.pop_front().unwrap();
+            self.stats.evict(&old);
+        }
+    }
+
+    /// Returns the statistics over the samples in the window.
+    pub fn stats(&self) -> &S {
+        &self.stats
+    }
+
+    /// Returns the length of time covered by the window.
+    pub fn duration(&self) -> f64 {
+        self.duration
+    }
+
+    /// Returns the number of samples in the window.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns true if there are no samples in the window.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+}
+
+/// Minimum and maximum of a stream that supports evicting old samples.
+///
+/// This keeps two monotonic deques, so adding and evicting take amortized
+/// constant time. Unlike `MinMax`, this must store samples that may still
+/// become the minimum or maximum.
+#[derive(Clone, Debug)]
+pub struct MovingMinMax<T> {
+    len: u64,
+    // Non-decreasing from front to back.
+    mins: VecDeque<T>,
+    // Non-increasing from front to back.
+    maxs: VecDeque<T>,
+}
+
+impl<T: PartialOrd + Clone> MovingMinMax<T> {
+    /// Create an empty state where min and max values do not exist.
+    pub fn new() -> MovingMinMax<T> {
+        Default::default()
+    }
+
+    /// Returns the minimum of the samples that have not been evicted.
+    pub fn min(&self) -> Option<&T> {
+        self.mins.front()
+    }
+
+    /// Returns the maximum of the samples that have not been evicted.
+    pub fn max(&self) -> Option<&T> {
+        self.maxs.front()
+    }
+
+    /// Returns the number of samples that have not been evicted.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Returns true if there are no samples.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T: PartialOrd + Clone> Evict<T> for MovingMinMax<T> {
+    fn insert(&mut self, sample: T) {
+        self.len += 1;
+        while self.mins.back().map(|v| v > &sample).unwrap_or(false) {
+            self.mins.pop_back();
+        }
+        self.mins.push_back(sample.clone());
+        while self.maxs.back().map(|v| v < &sample).unwrap_or(false) {
+            self.maxs.pop_back();
+        }
+        self.maxs.push_back(sample);
+    }
+
+    fn evict(&mut self, sample: &T) {
+        self.len -= 1;
+        // Any sample older than the front has already been dropped, so the
+        // front is only ever the oldest sample if they're equal.
+        if self.mins.front() == Some(sample) {
+            self.mins.pop_front();
+        }
+        if self.maxs.front() == Some(sample) {
+            self.maxs.pop_front();
+        }
+    }
+}
+
+impl<T: PartialOrd> Default for MovingMinMax<T> {
+    fn default() -> MovingMinMax<T> {
+        MovingMinMax {
+            len: 0,
+            mins: VecDeque::new(),
+            maxs: VecDeque::new(),
+        }
+    }
+}
+
+/// Median of a stream that supports evicting old samples.
+///
+/// The samples are split into a lower and an upper half, each kept in an
+/// ordered multiset, so adding and evicting take `O(log n)` time.
+#[derive(Clone, Debug)]
+pub struct MovingMedian<T> {
+    lower: BTreeMap<Partial<T>, u64>,
+    upper: BTreeMap<Partial<T>, u64>,
+    lower_len: u64,
+    upper_len: u64,
+}
+
+impl<T: PartialOrd + Clone> MovingMedian<T> {
+    /// Create initial empty state.
+    pub fn new() -> MovingMedian<T> {
+        Default::default()
+    }
+
+    /// Returns the number of samples that have not been evicted.
+    pub fn len(&self) -> usize {
+        (self.lower_len + self.upper_len) as usize
+    }
+
+    /// Returns true if there are no samples.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn rebalance(&mut self) {
+        // The lower half has either the same number of samples as the upper
+        // half, or one more.
+        if self.lower_len > self.upper_len + 1 {
+            let v = pop_last(&mut self.lower);
+            push(&mut self.upper, v);
+            self.lower_len -= 1;
+            self.upper_len += 1;
+        } else if self.upper_len > self.lower_len {
+            let v = pop_first(&mut self.upper);
+            push(&mut self.lower, v);
+            self.upper_len -= 1;
+            self.lower_len += 1;
+        }
+    }
+
+    fn in_lower(&self, sample: &Partial<T>) -> bool {
+        self.lower.keys().next_back().map(|max| sample <= max).unwrap_or(true)
+    }
+}
+
+impl<T: PartialOrd + ToPrimitive + Clone> MovingMedian<T> {
+    /// Returns the median of the samples that have not been evicted.
+    pub fn median(&self) -> Option<f64> {
+        let lo = match self.lower.keys().next_back() {
+            None => return None,
+            Some(lo) => lo.0.to_f64().unwrap(),
+        };
+        if self.lower_len > self.upper_len {
+            return Some(lo);
+        }
+        let hi = self.upper.keys().next().unwrap().0.to_f64().unwrap();
+        Some((lo + hi) / 2.0)
+    }
+}
+
+impl<T: PartialOrd + Clone> Evict<T> for MovingMedian<T> {
+    fn insert(&mut self, sample: T) {
+        let sample = Partial(sample);
+        if self.in_lower(&sample) {
+            push(&mut self.lower, sample);
+            self.lower_len += 1;
+        } else {
+            push(&mut self.upper, sample);
+            self.upper_len += 1;
+        }
+        self.rebalance();
+    }
+
+    fn evict(&mut self, sample: &T) {
+        let sample = Partial(sample.clone());
+        if self.in_lower(&sample) {
+            remove(&mut self.lower, &sample);
+            self.lower_len -= 1;
+        } else {
+            remove(&mut self.upper, &sample);
+            self.upper_len -= 1;
+        }
+        self.rebalance();
+    }
+}
+
+impl<T: PartialOrd> Default for MovingMedian<T> {
+    fn default() -> MovingMedian<T> {
+        MovingMedian {
+            lower: BTreeMap::new(),
+            upper: BTreeMap::new(),
+            lower_len: 0,
+            upper_len: 0,
+        }
+    }
+}
+
+fn push<K: Ord>(set: &mut BTreeMap<K, u64>, k: K) {
+    *set.entry(k).or_insert(0) += 1;
+}
+
+fn remove<K: Ord>(set: &mut BTreeMap<K, u64>, k: &K) {
+    let empty = {
+        let count = set.get_mut(k).expect("evicted a missing sample");
+        *count -= 1;
+        *count == 0
+    };
+    if empty {
+        set.remove(k);
+    }
+}
+
+fn pop_first<K: Ord + Clone>(set: &mut BTreeMap<K, u64>) -> K {
+    let k = set.keys().next().unwrap().clone();
+    remove(set, &k);
+    k
+}
+
+fn pop_last<K: Ord + Clone>(set: &mut BTreeMap<K, u64>) -> K {
+    let k = set.keys().next_back().unwrap().clone();
+    remove(set, &k);
+    k
+}
+
+#[cfg(test)]
+mod test {
+    use {OnlineStats, Unsorted};
+    use testutil::pseudo_random;
+    use super::{MovingMedian, MovingMinMax, TimeWindow, Window};
+
+    #[test]
+    fn window_stats() {
+        let xs = pseudo_random(200, 100);
+        let mut window = Window::new(10, OnlineStats::new());
+        for (i, &x) in xs.iter().enumerate() {
+            window.add(x);
+            let start = if i < 10 { 0 } else { i - 9 };
+            let expected = OnlineStats::from_slice(&xs[start..i + 1]);
+            assert_eq!(window.len(), i + 1 - start);
+            assert!((window.stats().mean() - expected.mean()).abs() < 1e-9);
+            assert!((window.stats().variance()
+                     - expected.variance()).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn window_minmax() {
+        let xs = pseudo_random(200, 100);
+        let mut window = Window::new(7, MovingMinMax::new());
+        for (i, &x) in xs.iter().enumerate() {
+            window.add(x);
+            let start = if i < 7 { 0 } else { i - 6 };
+            let slice = &xs[start..i + 1];
+            assert_eq!(window.stats().min(), slice.iter().min());
+            assert_eq!(window.stats().max(), slice.iter().max());
+        }
+    }
+
+    #[test]
+    fn window_median() {
+        let xs = pseudo_random(200, 100);
+        for &size in &[1, 2, 7, 10] {
+            let mut window = Window::new(size, MovingMedian::new());
+            for (i, &x) in xs.iter().enumerate() {
+                window.add(x);
+                let start = if i < size { 0 } else { i + 1 - size };
+                let mut expected: Unsorted<u64> =
+                    xs[start..i + 1].iter().cloned().collect();
+                assert_eq!(window.stats().median(), expected.median());
+            }
+        }
+    }
+
+    #[test]
+    fn time_window() {
+        let mut window = TimeWindow::new(10.0, MovingMinMax::new());
+        window.add(5, 0.0);
+        window.add(1, 3.0);
+        window.add(8, 9.0);
+        assert_eq!(window.stats().min(), Some(&1));
+        assert_eq!(window.stats().max(), Some(&8));
+        window.add(4, 10.0);
+        assert_eq!(window.len(), 3);
+        window.expire(13.0);
+        assert_eq!(window.len(), 2);
+        assert_eq!(window.stats().min(), Some(&4));
+        window.expire(100.0);
+        assert!(window.is_empty());
+        assert_eq!(window.stats().min(), None);
+    }
+}